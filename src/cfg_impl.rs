@@ -0,0 +1,1347 @@
+use std::{
+    collections::HashMap,
+    io::{self, Cursor, Read, Seek, SeekFrom, Write},
+};
+
+use rvff::rap::{Cfg, CfgClass, CfgEntry, CfgValue};
+
+use crate::{
+    pbo_impl::{read_cstring, read_u32},
+    preprocessor_impl::{IncludeResolver, Preprocessed, Preprocessor},
+};
+
+const RAP_MAGIC: &[u8; 4] = b"\0raP";
+
+/// Config value, independent of how it was read (rapified or text).
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ConfigValue {
+    String(String),
+    Float(f32),
+    Long(i32),
+    Int64(i64),
+    Expression(String),
+    Array(Vec<ConfigValue>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ConfigEntry {
+    /// `append` marks `name[] += {...}` arrays.
+    Property {
+        name: String,
+        value: ConfigValue,
+        append: bool,
+    },
+    Class(ConfigClass),
+    ExternClass(String),
+    DeleteClass(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct ConfigClass {
+    pub name: String,
+    pub parent: String,
+    pub entries: Vec<ConfigEntry>,
+}
+
+/// Whole config file, the root class has an empty name.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct Config {
+    pub root: ConfigClass,
+    pub enums: Vec<(String, i32)>,
+}
+
+impl From<&CfgValue> for ConfigValue {
+    fn from(value: &CfgValue) -> Self {
+        match value {
+            CfgValue::String(s) => ConfigValue::String(s.clone()),
+            CfgValue::Float(f) => ConfigValue::Float(*f),
+            CfgValue::Long(l) => ConfigValue::Long(*l),
+            CfgValue::Int64(l) => ConfigValue::Int64(*l),
+            CfgValue::Expression(e) => ConfigValue::Expression(e.clone()),
+            CfgValue::Array(values) => {
+                ConfigValue::Array(values.iter().map(|v| v.into()).collect())
+            }
+        }
+    }
+}
+
+impl From<&CfgEntry> for ConfigEntry {
+    fn from(entry: &CfgEntry) -> Self {
+        match entry {
+            CfgEntry::Property(property) => ConfigEntry::Property {
+                name: property.name.clone(),
+                value: (&property.value).into(),
+                append: false,
+            },
+            CfgEntry::Class(class) => ConfigEntry::Class(class.into()),
+            CfgEntry::ExternClass(name) => ConfigEntry::ExternClass(name.clone()),
+            CfgEntry::DeleteClass(name) => ConfigEntry::DeleteClass(name.clone()),
+        }
+    }
+}
+
+impl From<&CfgClass> for ConfigClass {
+    fn from(class: &CfgClass) -> Self {
+        Self {
+            name: class.name.clone(),
+            parent: class.parent.clone(),
+            entries: class.entries.iter().map(|entry| entry.into()).collect(),
+        }
+    }
+}
+
+impl ConfigEntry {
+    pub fn name(&self) -> &str {
+        match self {
+            ConfigEntry::Property { name, .. } => name,
+            ConfigEntry::Class(class) => &class.name,
+            ConfigEntry::ExternClass(name) | ConfigEntry::DeleteClass(name) => name,
+        }
+    }
+}
+
+/// Reads a rapified config, along with the enum table `Cfg` doesn't keep.
+pub(crate) fn read_rapified(buf: &[u8]) -> anyhow::Result<Config> {
+    let mut reader = Cursor::new(buf);
+    let cfg = Cfg::read(&mut reader)?;
+    let enums = read_enums(&mut reader)?;
+    Ok(Config {
+        root: (&cfg.inner_class).into(),
+        enums,
+    })
+}
+
+fn read_enums<R: Read + Seek>(reader: &mut R) -> anyhow::Result<Vec<(String, i32)>> {
+    reader.seek(SeekFrom::Start(12))?;
+    let enum_offset = read_u32(reader)?;
+    if enum_offset == 0 {
+        return Ok(Vec::new());
+    }
+
+    reader.seek(SeekFrom::Start(u64::from(enum_offset)))?;
+    let count = read_u32(reader)?;
+    let mut enums = Vec::new();
+    for _ in 0..count {
+        let name = read_cstring(reader)?;
+        enums.push((name, read_u32(reader)? as i32));
+    }
+    Ok(enums)
+}
+
+fn write_cstring<W: Write>(writer: &mut W, s: &str) -> io::Result<()> {
+    writer.write_all(s.as_bytes())?;
+    writer.write_all(&[0])
+}
+
+fn write_compressed_int<W: Write>(writer: &mut W, mut value: u32) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            return writer.write_all(&[byte]);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn value_type(value: &ConfigValue) -> u8 {
+    match value {
+        ConfigValue::String(_) => 0,
+        ConfigValue::Float(_) => 1,
+        ConfigValue::Long(_) => 2,
+        ConfigValue::Array(_) => 3,
+        ConfigValue::Expression(_) => 4,
+        ConfigValue::Int64(_) => 6,
+    }
+}
+
+fn write_value(buf: &mut Vec<u8>, value: &ConfigValue) -> io::Result<()> {
+    match value {
+        ConfigValue::String(s) | ConfigValue::Expression(s) => write_cstring(buf, s),
+        ConfigValue::Float(f) => buf.write_all(&f.to_le_bytes()),
+        ConfigValue::Long(l) => buf.write_all(&l.to_le_bytes()),
+        ConfigValue::Int64(l) => buf.write_all(&l.to_le_bytes()),
+        ConfigValue::Array(values) => write_array(buf, values),
+    }
+}
+
+fn write_array(buf: &mut Vec<u8>, values: &[ConfigValue]) -> io::Result<()> {
+    write_compressed_int(buf, values.len() as u32)?;
+    for value in values {
+        buf.push(value_type(value));
+        write_value(buf, value)?;
+    }
+    Ok(())
+}
+
+/// Writes a class body, followed by the bodies of its child classes.
+fn write_class_body(buf: &mut Vec<u8>, class: &ConfigClass) -> anyhow::Result<()> {
+    write_cstring(buf, &class.parent)?;
+    write_compressed_int(buf, class.entries.len() as u32)?;
+
+    let mut children = Vec::new();
+    for entry in &class.entries {
+        match entry {
+            ConfigEntry::Class(child) => {
+                buf.push(0);
+                write_cstring(buf, &child.name)?;
+                children.push((buf.len(), child));
+                buf.extend_from_slice(&0_u32.to_le_bytes());
+            }
+            ConfigEntry::Property {
+                name,
+                value: ConfigValue::Array(values),
+                append,
+            } => {
+                if *append {
+                    buf.push(5);
+                    buf.extend_from_slice(&1_u32.to_le_bytes());
+                } else {
+                    buf.push(2);
+                }
+                write_cstring(buf, name)?;
+                write_array(buf, values)?;
+            }
+            ConfigEntry::Property { name, value, .. } => {
+                buf.push(1);
+                buf.push(value_type(value));
+                write_cstring(buf, name)?;
+                write_value(buf, value)?;
+            }
+            ConfigEntry::ExternClass(name) => {
+                buf.push(3);
+                write_cstring(buf, name)?;
+            }
+            ConfigEntry::DeleteClass(name) => {
+                buf.push(4);
+                write_cstring(buf, name)?;
+            }
+        }
+    }
+
+    for (offset_pos, child) in children {
+        let offset = u32::try_from(buf.len())
+            .map_err(|_| anyhow::anyhow!("Config: Rapified output exceeds 4 GiB"))?;
+        buf[offset_pos..offset_pos + 4].copy_from_slice(&offset.to_le_bytes());
+        write_class_body(buf, child)?;
+    }
+    Ok(())
+}
+
+/// Serializes a config to the rapified (`config.bin`) format.
+pub(crate) fn rapify(config: &Config) -> anyhow::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(RAP_MAGIC);
+    buf.extend_from_slice(&0_u32.to_le_bytes());
+    buf.extend_from_slice(&8_u32.to_le_bytes());
+    buf.extend_from_slice(&0_u32.to_le_bytes());
+
+    write_class_body(&mut buf, &config.root)?;
+
+    let enum_offset = u32::try_from(buf.len())
+        .map_err(|_| anyhow::anyhow!("Config: Rapified output exceeds 4 GiB"))?;
+    buf[12..16].copy_from_slice(&enum_offset.to_le_bytes());
+    buf.extend_from_slice(&(config.enums.len() as u32).to_le_bytes());
+    for (name, value) in &config.enums {
+        write_cstring(&mut buf, name)?;
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+    Ok(buf)
+}
+
+fn class_entry_name(entry: &ConfigEntry) -> Option<&str> {
+    match entry {
+        ConfigEntry::Class(c) => Some(&c.name),
+        ConfigEntry::ExternClass(name) => Some(name),
+        _ => None,
+    }
+}
+
+/// Applies `patch` on top of `base` the way the game loads addon configs.
+///
+/// Properties are overridden (or extended for `+=` arrays), classes are merged recursively
+/// and take the parent of the redefinition, and `delete` removes the class from the merged tree.
+pub(crate) fn merge_class(base: &mut ConfigClass, patch: &ConfigClass) {
+    for entry in &patch.entries {
+        match entry {
+            ConfigEntry::Property {
+                name,
+                value,
+                append,
+            } => {
+                let existing = base.entries.iter_mut().find_map(|e| match e {
+                    ConfigEntry::Property { name: n, value, .. }
+                        if n.eq_ignore_ascii_case(name) =>
+                    {
+                        Some(value)
+                    }
+                    _ => None,
+                });
+                match (existing, value) {
+                    (Some(ConfigValue::Array(values)), ConfigValue::Array(added)) if *append => {
+                        values.extend(added.iter().cloned())
+                    }
+                    (Some(existing), _) => *existing = value.clone(),
+                    (None, _) => base.entries.push(entry.clone()),
+                }
+            }
+            ConfigEntry::Class(class) => {
+                let existing = base.entries.iter().position(|e| {
+                    class_entry_name(e).is_some_and(|n| n.eq_ignore_ascii_case(&class.name))
+                });
+                match existing.map(|i| &mut base.entries[i]) {
+                    Some(ConfigEntry::Class(existing)) => {
+                        existing.parent = class.parent.clone();
+                        merge_class(existing, class);
+                    }
+                    Some(extern_class) => *extern_class = entry.clone(),
+                    None => base.entries.push(entry.clone()),
+                }
+            }
+            ConfigEntry::ExternClass(name) => {
+                if !base
+                    .entries
+                    .iter()
+                    .any(|e| class_entry_name(e).is_some_and(|n| n.eq_ignore_ascii_case(name)))
+                {
+                    base.entries.push(entry.clone());
+                }
+            }
+            ConfigEntry::DeleteClass(name) => base.entries.retain(
+                |e| !matches!(e, ConfigEntry::Class(c) if c.name.eq_ignore_ascii_case(name)),
+            ),
+        }
+    }
+}
+
+/// Merges a config into `base`, see [`merge_class`].
+pub(crate) fn merge_config(base: &mut Config, patch: &Config) {
+    merge_class(&mut base.root, &patch.root);
+    for (name, value) in &patch.enums {
+        match base.enums.iter_mut().find(|(n, _)| n == name) {
+            Some(existing) => existing.1 = *value,
+            None => base.enums.push((name.clone(), *value)),
+        }
+    }
+}
+
+/// A class together with its enclosing classes, starting at the root.
+type ClassScope<'a> = Vec<&'a ConfigClass>;
+
+const MAX_INHERITANCE_DEPTH: usize = 64;
+
+/// Finds a property or class defined directly in `class`, names are case-insensitive.
+fn own_member<'a>(
+    class: &'a ConfigClass,
+    name: &str,
+    skip: Option<&ConfigClass>,
+) -> Option<&'a ConfigEntry> {
+    class.entries.iter().rev().find(|e| match e {
+        ConfigEntry::Property { name: n, .. } => n.eq_ignore_ascii_case(name),
+        ConfigEntry::Class(c) => {
+            c.name.eq_ignore_ascii_case(name) && !skip.is_some_and(|s| std::ptr::eq(s, c))
+        }
+        _ => false,
+    })
+}
+
+/// Finds a member of the last class in `scope`, following `class X: Y` parents.
+///
+/// `skip` excludes a class of the scope's own entries, so `class A: A` refers to an inherited `A`.
+/// Returns the scope of the class that defines the member.
+fn find_member<'a>(
+    scope: &[&'a ConfigClass],
+    name: &str,
+    skip: Option<&ConfigClass>,
+    depth: usize,
+) -> Option<(ClassScope<'a>, &'a ConfigEntry)> {
+    let class = scope.last()?;
+    if let Some(entry) = own_member(class, name, skip) {
+        return Some((scope.to_vec(), entry));
+    }
+
+    if class.parent.is_empty() || depth >= MAX_INHERITANCE_DEPTH {
+        return None;
+    }
+    let parent = resolve_parent(&scope[..scope.len() - 1], class, depth + 1)?;
+    find_member(&parent, name, None, depth + 1)
+}
+
+/// Resolves the parent of `class`, searching its enclosing classes from the inside out.
+fn resolve_parent<'a>(
+    scope: &[&'a ConfigClass],
+    class: &ConfigClass,
+    depth: usize,
+) -> Option<ClassScope<'a>> {
+    (1..=scope.len()).rev().find_map(|end| {
+        match find_member(&scope[..end], &class.parent, Some(class), depth)? {
+            (mut found, ConfigEntry::Class(parent)) => {
+                found.push(parent);
+                Some(found)
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Looks up a path through class inheritance.
+///
+/// Returns the entry and the path of the class that defines it.
+pub(crate) fn find_inherited<'a>(
+    root: &'a ConfigClass,
+    path: &[String],
+) -> Option<(&'a ConfigEntry, Vec<String>)> {
+    let (last, classes) = path.split_last()?;
+    let mut scope = vec![root];
+    for name in classes {
+        match find_member(&scope, name, None, 0)? {
+            (mut found, ConfigEntry::Class(class)) => {
+                found.push(class);
+                scope = found;
+            }
+            _ => return None,
+        }
+    }
+
+    let (found, entry) = find_member(&scope, last, None, 0)?;
+    Some((
+        entry,
+        found.iter().skip(1).map(|c| c.name.clone()).collect(),
+    ))
+}
+
+/// Looks up a path literally, without following inheritance.
+pub(crate) fn find_entry<'a>(class: &'a ConfigClass, path: &[String]) -> Option<&'a ConfigEntry> {
+    let (last, classes) = path.split_last()?;
+    let mut class = class;
+    for name in classes {
+        match own_member(class, name, None)? {
+            ConfigEntry::Class(child) => class = child,
+            _ => return None,
+        }
+    }
+    own_member(class, last, None)
+}
+
+/// Looks up a class literally, an empty path is the class itself.
+pub(crate) fn find_class<'a>(class: &'a ConfigClass, path: &[String]) -> Option<&'a ConfigClass> {
+    if path.is_empty() {
+        return Some(class);
+    }
+    match find_entry(class, path)? {
+        ConfigEntry::Class(class) => Some(class),
+        _ => None,
+    }
+}
+
+/// Visits the entries of a class depth-first, a class's entries follow right after it.
+///
+/// `path` is extended with each entry's name while the visitor is called.
+pub(crate) fn visit_entries(
+    class: &ConfigClass,
+    path: &mut Vec<String>,
+    visitor: &mut dyn FnMut(&[String], &ConfigEntry),
+) {
+    for entry in &class.entries {
+        path.push(entry.name().to_string());
+        visitor(path, entry);
+        if let ConfigEntry::Class(child) = entry {
+            visit_entries(child, path, visitor);
+        }
+        path.pop();
+    }
+}
+
+const TEXT_INDENT: &str = "    ";
+
+/// Quotes a string, doubling embedded quotes.
+fn quote_text(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+/// Formats a float so that it parses back to the same `f32`, and not as a `Long`.
+///
+/// Uses the shortest round-trip digits, with an exponent for very large and small values.
+fn float_text(value: f32) -> anyhow::Result<String> {
+    if !value.is_finite() {
+        return Err(anyhow::anyhow!(
+            "Config: Can't write non-finite float {}",
+            value
+        ));
+    }
+
+    let magnitude = value.abs();
+    if magnitude != 0.0 && !(1e-5..1e15).contains(&magnitude) {
+        return Ok(format!("{:e}", value));
+    }
+    let text = value.to_string();
+    Ok(if text.contains('.') {
+        text
+    } else {
+        format!("{}.0", text)
+    })
+}
+
+fn value_text(value: &ConfigValue) -> anyhow::Result<String> {
+    Ok(match value {
+        ConfigValue::String(s) => quote_text(s),
+        ConfigValue::Expression(s) => s.clone(),
+        ConfigValue::Float(f) => float_text(*f)?,
+        ConfigValue::Long(l) => l.to_string(),
+        ConfigValue::Int64(l) => l.to_string(),
+        ConfigValue::Array(values) => format!(
+            "{{{}}}",
+            values
+                .iter()
+                .map(value_text)
+                .collect::<anyhow::Result<Vec<_>>>()?
+                .join(", ")
+        ),
+    })
+}
+
+fn write_class_text(out: &mut String, class: &ConfigClass, depth: usize) -> anyhow::Result<()> {
+    let indent = TEXT_INDENT.repeat(depth);
+    for entry in &class.entries {
+        match entry {
+            ConfigEntry::Property {
+                name,
+                value,
+                append,
+            } => {
+                let operator = match (value, append) {
+                    (ConfigValue::Array(_), true) => "[] +=",
+                    (ConfigValue::Array(_), false) => "[] =",
+                    _ => " =",
+                };
+                let value =
+                    value_text(value).map_err(|e| anyhow::anyhow!("{} (property {})", e, name))?;
+                out.push_str(&format!("{}{}{} {};\n", indent, name, operator, value));
+            }
+            ConfigEntry::Class(child) => {
+                let parent = if child.parent.is_empty() {
+                    String::new()
+                } else {
+                    format!(": {}", child.parent)
+                };
+                if child.entries.is_empty() {
+                    out.push_str(&format!("{}class {}{} {{}};\n", indent, child.name, parent));
+                } else {
+                    out.push_str(&format!("{}class {}{} {{\n", indent, child.name, parent));
+                    write_class_text(out, child, depth + 1)?;
+                    out.push_str(&format!("{}}};\n", indent));
+                }
+            }
+            ConfigEntry::ExternClass(name) => out.push_str(&format!("{}class {};\n", indent, name)),
+            ConfigEntry::DeleteClass(name) => {
+                out.push_str(&format!("{}delete {};\n", indent, name))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Pretty-prints a config as `config.cpp` text, failing on floats that have no text form.
+pub(crate) fn to_text(config: &Config) -> anyhow::Result<String> {
+    let mut out = String::new();
+    if !config.enums.is_empty() {
+        out.push_str("enum {\n");
+        for (name, value) in &config.enums {
+            out.push_str(&format!("{}{} = {},\n", TEXT_INDENT, name, value));
+        }
+        out.push_str("};\n");
+    }
+    write_class_text(&mut out, &config.root, 0)?;
+    Ok(out)
+}
+
+const JSON_PARENT: &str = "__parent";
+const JSON_ENUMS: &str = "__enums";
+const JSON_DELETE: &str = "__delete";
+const JSON_INT64: &str = "__int64";
+const JSON_EXPRESSION: &str = "__expression";
+const JSON_APPEND_SUFFIX: &str = "+=";
+/// Prefix of the keys `to_json` adds itself, entry names starting with it get an extra `_`.
+const JSON_RESERVED_PREFIX: &str = "__";
+/// Separates the occurrence of a repeated entry name, `X#1` is the second `X` of a class.
+const JSON_DUPLICATE_SEPARATOR: char = '#';
+
+fn value_to_json(value: &ConfigValue) -> anyhow::Result<serde_json::Value> {
+    use serde_json::{json, Value};
+
+    Ok(match value {
+        ConfigValue::String(s) => Value::String(s.clone()),
+        ConfigValue::Float(f) if !f.is_finite() => {
+            return Err(anyhow::anyhow!(
+                "Config: Can't export non-finite float {}",
+                f
+            ))
+        }
+        ConfigValue::Float(f) => json!(f64::from(*f)),
+        ConfigValue::Long(l) => json!(l),
+        ConfigValue::Int64(l) => json!({ JSON_INT64: l }),
+        ConfigValue::Expression(e) => json!({ JSON_EXPRESSION: e }),
+        ConfigValue::Array(values) => Value::Array(
+            values
+                .iter()
+                .map(value_to_json)
+                .collect::<anyhow::Result<_>>()?,
+        ),
+    })
+}
+
+/// Key of an entry, escaped and numbered so that no entry overwrites another.
+fn entry_json_key(
+    name: &str,
+    append: bool,
+    seen: &mut HashMap<String, usize>,
+) -> anyhow::Result<String> {
+    if name.contains(JSON_DUPLICATE_SEPARATOR) || name.ends_with(JSON_APPEND_SUFFIX) {
+        return Err(anyhow::anyhow!(
+            "Config: Can't export entry name '{}' to JSON",
+            name
+        ));
+    }
+
+    let mut key = if name.starts_with(JSON_RESERVED_PREFIX) {
+        format!("_{}", name)
+    } else {
+        name.to_string()
+    };
+    if append {
+        key.push_str(JSON_APPEND_SUFFIX);
+    }
+    let count = seen.entry(key.clone()).or_default();
+    *count += 1;
+    if *count > 1 {
+        key = format!("{}{}{}", key, JSON_DUPLICATE_SEPARATOR, *count - 1);
+    }
+    Ok(key)
+}
+
+fn class_to_json(
+    class: &ConfigClass,
+) -> anyhow::Result<serde_json::Map<String, serde_json::Value>> {
+    use serde_json::{json, Value};
+
+    let mut object = serde_json::Map::new();
+    if !class.parent.is_empty() {
+        object.insert(JSON_PARENT.to_string(), json!(class.parent));
+    }
+    let mut seen = HashMap::new();
+    for entry in &class.entries {
+        let append = matches!(entry, ConfigEntry::Property { append: true, .. });
+        let key = entry_json_key(entry.name(), append, &mut seen)?;
+        let value = match entry {
+            ConfigEntry::Property { name, value, .. } => {
+                value_to_json(value).map_err(|e| anyhow::anyhow!("{} (property {})", e, name))?
+            }
+            ConfigEntry::Class(child) => Value::Object(class_to_json(child)?),
+            ConfigEntry::ExternClass(_) => Value::Null,
+            ConfigEntry::DeleteClass(_) => json!({ JSON_DELETE: true }),
+        };
+        object.insert(key, value);
+    }
+    Ok(object)
+}
+
+/// Converts a config to a JSON value, classes become objects in entry order.
+///
+/// Class parents and the root enums are stored under `__parent` and `__enums`. Extern classes
+/// are `null`, deleted classes `{"__delete": true}`, int64 and expression values are wrapped in
+/// `{"__int64": ..}` and `{"__expression": ..}`, and `+=` arrays get a `+=` key suffix.
+/// Entry names starting with `__` get an extra `_`, and repeated names (`class X;` followed by
+/// `class X {}`) are numbered from the second one on, as `X#1`, `X#2`. Non-finite floats and
+/// names containing `#` or ending in `+=` can't be exported.
+pub(crate) fn to_json(config: &Config) -> anyhow::Result<serde_json::Value> {
+    let mut object = class_to_json(&config.root)?;
+    if !config.enums.is_empty() {
+        let enums = config
+            .enums
+            .iter()
+            .map(|(name, value)| (name.clone(), serde_json::json!(value)))
+            .collect();
+        object.insert(JSON_ENUMS.to_string(), serde_json::Value::Object(enums));
+    }
+    Ok(serde_json::Value::Object(object))
+}
+
+/// Returns the single value of a `{"__key": value}` wrapper object.
+fn json_wrapped<'a>(
+    object: &'a serde_json::Map<String, serde_json::Value>,
+    key: &str,
+) -> Option<&'a serde_json::Value> {
+    if object.len() == 1 {
+        object.get(key)
+    } else {
+        None
+    }
+}
+
+fn value_from_json(value: &serde_json::Value) -> anyhow::Result<ConfigValue> {
+    use serde_json::Value;
+
+    Ok(match value {
+        Value::String(s) => ConfigValue::String(s.clone()),
+        Value::Number(n) => match n.as_i64() {
+            Some(l) => match i32::try_from(l) {
+                Ok(l) => ConfigValue::Long(l),
+                Err(_) => ConfigValue::Int64(l),
+            },
+            None => ConfigValue::Float(n.as_f64().unwrap_or_default() as f32),
+        },
+        Value::Array(values) => ConfigValue::Array(
+            values
+                .iter()
+                .map(value_from_json)
+                .collect::<anyhow::Result<_>>()?,
+        ),
+        Value::Object(object) => {
+            if let Some(l) = json_wrapped(object, JSON_INT64).and_then(|v| v.as_i64()) {
+                ConfigValue::Int64(l)
+            } else if let Some(e) = json_wrapped(object, JSON_EXPRESSION).and_then(|v| v.as_str()) {
+                ConfigValue::Expression(e.to_string())
+            } else {
+                return Err(anyhow::anyhow!("Config: Unexpected object value {}", value));
+            }
+        }
+        Value::Null | Value::Bool(_) => {
+            return Err(anyhow::anyhow!("Config: Unsupported value {}", value))
+        }
+    })
+}
+
+/// Reverses [`entry_json_key`], returning the entry name and whether it's a `+=` array.
+fn entry_name_from_json(key: &str) -> anyhow::Result<(String, bool)> {
+    let key = match key.rsplit_once(JSON_DUPLICATE_SEPARATOR) {
+        Some((key, n)) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => key,
+        _ => key,
+    };
+    let (key, append) = match key.strip_suffix(JSON_APPEND_SUFFIX) {
+        Some(key) => (key, true),
+        None => (key, false),
+    };
+    match key.strip_prefix('_') {
+        Some(name) if name.starts_with(JSON_RESERVED_PREFIX) => Ok((name.to_string(), append)),
+        _ if key.starts_with(JSON_RESERVED_PREFIX) => {
+            Err(anyhow::anyhow!("Config: Unknown reserved key {}", key))
+        }
+        _ => Ok((key.to_string(), append)),
+    }
+}
+
+fn class_from_json(
+    name: &str,
+    object: &serde_json::Map<String, serde_json::Value>,
+    root: bool,
+) -> anyhow::Result<ConfigClass> {
+    use serde_json::Value;
+
+    let mut class = ConfigClass {
+        name: name.to_string(),
+        ..Default::default()
+    };
+    for (key, value) in object {
+        if key == JSON_PARENT {
+            class.parent = value
+                .as_str()
+                .ok_or_else(|| anyhow::anyhow!("Config: {} of {} isn't a string", key, name))?
+                .to_string();
+            continue;
+        }
+        if root && key == JSON_ENUMS {
+            continue;
+        }
+
+        let (entry_name, append) = entry_name_from_json(key)?;
+        let entry = match value {
+            _ if append => {
+                if !value.is_array() {
+                    return Err(anyhow::anyhow!("Config: {} isn't an array", key));
+                }
+                ConfigEntry::Property {
+                    name: entry_name,
+                    value: value_from_json(value)?,
+                    append: true,
+                }
+            }
+            Value::Null => ConfigEntry::ExternClass(entry_name),
+            Value::Object(child) if json_wrapped(child, JSON_DELETE).is_some() => {
+                ConfigEntry::DeleteClass(entry_name)
+            }
+            Value::Object(child)
+                if json_wrapped(child, JSON_INT64).is_none()
+                    && json_wrapped(child, JSON_EXPRESSION).is_none() =>
+            {
+                ConfigEntry::Class(class_from_json(&entry_name, child, false)?)
+            }
+            _ => ConfigEntry::Property {
+                name: entry_name,
+                value: value_from_json(value)?,
+                append: false,
+            },
+        };
+        class.entries.push(entry);
+    }
+    Ok(class)
+}
+
+/// Builds a config back from the JSON layout written by [`to_json`].
+pub(crate) fn from_json(value: &serde_json::Value) -> anyhow::Result<Config> {
+    let object = value
+        .as_object()
+        .ok_or_else(|| anyhow::anyhow!("Config: JSON root must be an object"))?;
+
+    let mut enums = Vec::new();
+    if let Some(values) = object.get(JSON_ENUMS) {
+        let values = values
+            .as_object()
+            .ok_or_else(|| anyhow::anyhow!("Config: {} must be an object", JSON_ENUMS))?;
+        for (name, value) in values {
+            let value = value
+                .as_i64()
+                .and_then(|v| i32::try_from(v).ok())
+                .ok_or_else(|| anyhow::anyhow!("Config: Enum value of {} isn't a number", name))?;
+            enums.push((name.clone(), value));
+        }
+    }
+
+    Ok(Config {
+        root: class_from_json("", object, true)?,
+        enums,
+    })
+}
+
+/// Value of an `__EVAL`/`__EXEC` expression.
+#[derive(Debug, Clone)]
+enum EvalValue {
+    Number(f64),
+    String(String),
+}
+
+impl From<EvalValue> for ConfigValue {
+    fn from(value: EvalValue) -> Self {
+        match value {
+            EvalValue::Number(n)
+                if n.fract() == 0.0 && n >= f64::from(i32::MIN) && n <= f64::from(i32::MAX) =>
+            {
+                ConfigValue::Long(n as i32)
+            }
+            EvalValue::Number(n) => ConfigValue::Float(n as f32),
+            EvalValue::String(s) => ConfigValue::String(s),
+        }
+    }
+}
+
+/// Arithmetic and string concatenation for `__EVAL`, with variables set by `__EXEC`.
+struct ExprParser<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    variables: &'a HashMap<String, EvalValue>,
+}
+
+impl ExprParser<'_> {
+    fn skip_ws(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_ws();
+        if self.chars.get(self.pos) == Some(&c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse(mut self) -> anyhow::Result<EvalValue> {
+        let value = self.expr()?;
+        self.skip_ws();
+        if self.pos < self.chars.len() {
+            return Err(anyhow::anyhow!(
+                "Unexpected '{}' in expression",
+                self.chars[self.pos]
+            ));
+        }
+        Ok(value)
+    }
+
+    fn number(value: EvalValue, op: char) -> anyhow::Result<f64> {
+        match value {
+            EvalValue::Number(n) => Ok(n),
+            EvalValue::String(_) => Err(anyhow::anyhow!("Operator '{}' needs numbers", op)),
+        }
+    }
+
+    fn expr(&mut self) -> anyhow::Result<EvalValue> {
+        let mut value = self.term()?;
+        loop {
+            if self.eat('+') {
+                value = match (value, self.term()?) {
+                    (EvalValue::Number(a), EvalValue::Number(b)) => EvalValue::Number(a + b),
+                    (EvalValue::String(a), EvalValue::String(b)) => EvalValue::String(a + &b),
+                    _ => return Err(anyhow::anyhow!("Can't add a number and a string")),
+                };
+            } else if self.eat('-') {
+                let b = Self::number(self.term()?, '-')?;
+                value = EvalValue::Number(Self::number(value, '-')? - b);
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn term(&mut self) -> anyhow::Result<EvalValue> {
+        let mut value = self.power()?;
+        loop {
+            let op = ['*', '/', '%'].into_iter().find(|op| self.eat(*op));
+            let Some(op) = op else {
+                return Ok(value);
+            };
+            let b = Self::number(self.power()?, op)?;
+            let a = Self::number(value, op)?;
+            value = EvalValue::Number(match op {
+                '*' => a * b,
+                '/' => a / b,
+                _ => a % b,
+            });
+        }
+    }
+
+    fn power(&mut self) -> anyhow::Result<EvalValue> {
+        let value = self.unary()?;
+        if self.eat('^') {
+            let b = Self::number(self.power()?, '^')?;
+            return Ok(EvalValue::Number(Self::number(value, '^')?.powf(b)));
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> anyhow::Result<EvalValue> {
+        if self.eat('-') {
+            return Ok(EvalValue::Number(-Self::number(self.unary()?, '-')?));
+        }
+        if self.eat('+') {
+            return self.unary();
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> anyhow::Result<EvalValue> {
+        if self.eat('(') {
+            let value = self.expr()?;
+            if !self.eat(')') {
+                return Err(anyhow::anyhow!("Missing ')' in expression"));
+            }
+            return Ok(value);
+        }
+
+        self.skip_ws();
+        let start = self.pos;
+        match self.chars.get(self.pos) {
+            Some('"') | Some('\'') => {
+                let quote = self.chars[self.pos];
+                let mut s = String::new();
+                self.pos += 1;
+                loop {
+                    match self.chars.get(self.pos) {
+                        None => return Err(anyhow::anyhow!("Unterminated string in expression")),
+                        Some(&c) if c == quote => {
+                            self.pos += 1;
+                            if self.chars.get(self.pos) != Some(&quote) {
+                                return Ok(EvalValue::String(s));
+                            }
+                            s.push(quote);
+                        }
+                        Some(&c) => s.push(c),
+                    }
+                    self.pos += 1;
+                }
+            }
+            Some(c) if c.is_ascii_digit() || *c == '.' => {
+                while self
+                    .chars
+                    .get(self.pos)
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || *c == '.')
+                {
+                    self.pos += 1;
+                }
+                let raw: String = self.chars[start..self.pos].iter().collect();
+                match parse_number(&raw) {
+                    Some(ConfigValue::Long(n)) => Ok(EvalValue::Number(f64::from(n))),
+                    Some(ConfigValue::Int64(n)) => Ok(EvalValue::Number(n as f64)),
+                    Some(ConfigValue::Float(n)) => Ok(EvalValue::Number(f64::from(n))),
+                    _ => Err(anyhow::anyhow!("Invalid number '{}'", raw)),
+                }
+            }
+            Some(c) if c.is_ascii_alphabetic() || *c == '_' => {
+                while self
+                    .chars
+                    .get(self.pos)
+                    .is_some_and(|c| c.is_ascii_alphanumeric() || *c == '_')
+                {
+                    self.pos += 1;
+                }
+                let name: String = self.chars[start..self.pos].iter().collect();
+                self.variables
+                    .get(&name)
+                    .cloned()
+                    .ok_or_else(|| anyhow::anyhow!("Unknown variable '{}'", name))
+            }
+            _ => Err(anyhow::anyhow!("Expected a value in expression")),
+        }
+    }
+}
+
+/// Parses unquoted numbers, decimal and hexadecimal integers become `Long` (or `Int64`).
+fn parse_number(raw: &str) -> Option<ConfigValue> {
+    let (negative, digits) = match raw.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, raw.strip_prefix('+').unwrap_or(raw)),
+    };
+
+    let integer = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None if digits.chars().all(|c| c.is_ascii_digit()) => digits.parse::<i64>().ok(),
+        None => None,
+    };
+    if let Some(integer) = integer {
+        let integer = if negative { -integer } else { integer };
+        return Some(match i32::try_from(integer) {
+            Ok(long) => ConfigValue::Long(long),
+            Err(_) => ConfigValue::Int64(integer),
+        });
+    }
+
+    let is_float = digits.chars().any(|c| c.is_ascii_digit())
+        && digits
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-'));
+    if !is_float {
+        return None;
+    }
+    raw.parse::<f32>().ok().map(ConfigValue::Float)
+}
+
+/// Recursive descent parser for preprocessed config text.
+struct TextParser<'a> {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    source: &'a Preprocessed,
+    variables: HashMap<String, EvalValue>,
+    enums: Vec<(String, i32)>,
+}
+
+impl TextParser<'_> {
+    fn error(&self, message: impl std::fmt::Display) -> anyhow::Error {
+        anyhow::anyhow!("Config: {}: {}", self.source.location(self.line), message)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        if c == '\n' {
+            self.line += 1;
+        }
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn skip_ws(&mut self) {
+        while self.peek().is_some_and(|c| c.is_whitespace()) {
+            self.bump();
+        }
+    }
+
+    fn expect(&mut self, c: char) -> anyhow::Result<()> {
+        self.skip_ws();
+        match self.peek() {
+            Some(found) if found == c => {
+                self.bump();
+                Ok(())
+            }
+            Some(found) => Err(self.error(format!("Expected '{}', found '{}'", c, found))),
+            None => Err(self.error(format!("Expected '{}', found end of file", c))),
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_ws();
+        if self.peek() == Some(c) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn ident(&mut self) -> anyhow::Result<String> {
+        self.skip_ws();
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            self.bump();
+        }
+        if start == self.pos {
+            return Err(match self.peek() {
+                Some(c) => self.error(format!("Unexpected '{}'", c)),
+                None => self.error("Unexpected end of file"),
+            });
+        }
+        Ok(self.chars[start..self.pos].iter().collect())
+    }
+
+    /// Returns the text inside balanced parentheses, the opening one already consumed.
+    fn parenthesized(&mut self) -> anyhow::Result<String> {
+        let start = self.pos;
+        let mut depth = 0;
+        loop {
+            match self.bump() {
+                Some('(') => depth += 1,
+                Some(')') if depth == 0 => break,
+                Some(')') => depth -= 1,
+                Some('"') => while self.bump().is_some_and(|c| c != '"') {},
+                Some(_) => {}
+                None => return Err(self.error("Unclosed '('")),
+            }
+        }
+        Ok(self.chars[start..self.pos - 1].iter().collect())
+    }
+
+    fn evaluate(&self, expression: &str) -> anyhow::Result<EvalValue> {
+        ExprParser {
+            chars: expression.chars().collect(),
+            pos: 0,
+            variables: &self.variables,
+        }
+        .parse()
+        .map_err(|e| self.error(format!("__EVAL: {}", e)))
+    }
+
+    fn execute(&mut self, statements: &str) -> anyhow::Result<()> {
+        for statement in statements.split(';').filter(|s| !s.trim().is_empty()) {
+            let (name, expression) = statement.split_once('=').ok_or_else(|| {
+                self.error(format!("__EXEC: Expected assignment in '{}'", statement))
+            })?;
+            let value = self
+                .evaluate(expression)
+                .map_err(|e| self.error(format!("__EXEC: {}", e)))?;
+            self.variables.insert(name.trim().to_string(), value);
+        }
+        Ok(())
+    }
+
+    fn end_statement(&mut self) -> anyhow::Result<()> {
+        if !self.eat(';') && self.peek() != Some('}') {
+            return Err(match self.peek() {
+                Some(c) => self.error(format!("Expected ';', found '{}'", c)),
+                None => self.error("Expected ';', found end of file"),
+            });
+        }
+        Ok(())
+    }
+
+    fn parse_entries(&mut self, in_class: bool) -> anyhow::Result<Vec<ConfigEntry>> {
+        let mut entries = Vec::new();
+        loop {
+            self.skip_ws();
+            match self.peek() {
+                None if in_class => return Err(self.error("Missing '}'")),
+                None => return Ok(entries),
+                Some('}') if in_class => return Ok(entries),
+                Some(';') => {
+                    self.bump();
+                    continue;
+                }
+                _ => {}
+            }
+
+            let word = self.ident()?;
+            match word.as_str() {
+                "class" => {
+                    let name = self.ident()?;
+                    let parent = if self.eat(':') {
+                        self.ident()?
+                    } else {
+                        String::new()
+                    };
+                    if self.eat('{') {
+                        let class_entries = self.parse_entries(true)?;
+                        self.expect('}')?;
+                        self.end_statement()?;
+                        entries.push(ConfigEntry::Class(ConfigClass {
+                            name,
+                            parent,
+                            entries: class_entries,
+                        }));
+                    } else {
+                        self.end_statement()?;
+                        entries.push(ConfigEntry::ExternClass(name));
+                    }
+                }
+                "delete" => {
+                    entries.push(ConfigEntry::DeleteClass(self.ident()?));
+                    self.end_statement()?;
+                }
+                "enum" => {
+                    self.parse_enum()?;
+                    self.end_statement()?;
+                }
+                "__EXEC" => {
+                    self.expect('(')?;
+                    let statements = self.parenthesized()?;
+                    self.execute(&statements)?;
+                }
+                _ => {
+                    let is_array = self.eat('[');
+                    if is_array {
+                        self.expect(']')?;
+                    }
+                    let append = is_array && self.eat('+');
+                    self.expect('=')?;
+
+                    self.skip_ws();
+                    let value = if is_array && self.peek() == Some('{') {
+                        self.parse_array()?
+                    } else {
+                        self.parse_value(&[';', '}'])?
+                    };
+                    self.end_statement()?;
+                    entries.push(ConfigEntry::Property {
+                        name: word,
+                        value,
+                        append,
+                    });
+                }
+            }
+        }
+    }
+
+    fn parse_enum(&mut self) -> anyhow::Result<()> {
+        self.expect('{')?;
+        let mut next = 0;
+        while !self.eat('}') {
+            let name = self.ident()?;
+            if self.eat('=') {
+                next = match self.parse_value(&[',', '}'])? {
+                    ConfigValue::Long(value) => value,
+                    _ => return Err(self.error(format!("Enum value of {} isn't a number", name))),
+                };
+            }
+            self.enums.push((name, next));
+            next = next.wrapping_add(1);
+            if !self.eat(',') && self.peek() != Some('}') {
+                return Err(self.error("Expected ',' or '}' in enum"));
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_array(&mut self) -> anyhow::Result<ConfigValue> {
+        self.expect('{')?;
+        let mut values = Vec::new();
+        loop {
+            self.skip_ws();
+            if self.eat('}') {
+                break;
+            }
+            let value = if self.peek() == Some('{') {
+                self.parse_array()?
+            } else {
+                self.parse_value(&[',', '}'])?
+            };
+            values.push(value);
+            if !self.eat(',') && self.peek() != Some('}') {
+                return Err(self.error("Expected ',' or '}' in array"));
+            }
+        }
+        Ok(ConfigValue::Array(values))
+    }
+
+    /// Parses a quoted string, `__EVAL(...)`, number or unquoted string up to a terminator.
+    fn parse_value(&mut self, terminators: &[char]) -> anyhow::Result<ConfigValue> {
+        self.skip_ws();
+        if self.peek() == Some('"') {
+            self.bump();
+            let mut s = String::new();
+            loop {
+                match self.bump() {
+                    Some('"') if self.peek() == Some('"') => {
+                        self.bump();
+                        s.push('"');
+                    }
+                    Some('"') => return Ok(ConfigValue::String(s)),
+                    Some(c) => s.push(c),
+                    None => return Err(self.error("Unterminated string")),
+                }
+            }
+        }
+
+        // Terminators inside parentheses belong to the value, like the commas of `__EVAL(f(1, 2))`.
+        let start = self.pos;
+        let mut depth = 0_usize;
+        while let Some(c) = self.peek() {
+            if c == '\n' || (depth == 0 && terminators.contains(&c)) {
+                break;
+            }
+            self.bump();
+            match c {
+                '(' => depth += 1,
+                ')' => depth = depth.saturating_sub(1),
+                '"' if depth > 0 => while self.bump().is_some_and(|c| c != '"' && c != '\n') {},
+                _ => {}
+            }
+        }
+        let raw: String = self.chars[start..self.pos].iter().collect();
+        let raw = raw.trim();
+
+        if let Some(rest) = raw.strip_prefix("__EVAL") {
+            let expression = rest
+                .trim_start()
+                .strip_prefix('(')
+                .and_then(|r| r.strip_suffix(')'))
+                .ok_or_else(|| self.error("Invalid __EVAL"))?;
+            return Ok(self.evaluate(expression)?.into());
+        }
+        Ok(parse_number(raw).unwrap_or_else(|| ConfigValue::String(raw.to_string())))
+    }
+}
+
+/// Preprocesses and parses a text config (`config.cpp`, `.hpp`, `mission.sqm`).
+pub(crate) fn parse_text(
+    file: &str,
+    content: &str,
+    resolver: &dyn IncludeResolver,
+) -> anyhow::Result<Config> {
+    let source = Preprocessor::new(resolver).run(file, content)?;
+    let mut parser = TextParser {
+        chars: source.text.chars().collect(),
+        pos: 0,
+        line: 0,
+        source: &source,
+        variables: HashMap::new(),
+        enums: Vec::new(),
+    };
+
+    let entries = parser.parse_entries(false)?;
+    Ok(Config {
+        root: ConfigClass {
+            name: String::new(),
+            parent: String::new(),
+            entries,
+        },
+        enums: parser.enums,
+    })
+}
@@ -1,27 +1,45 @@
+mod cfg_impl;
 mod core_impl;
 mod enum_impl;
+mod mods_impl;
 mod odol_impl;
 mod oprw_impl;
 mod paa_impl;
 mod pbo_impl;
+mod preprocessor_impl;
+mod sign_impl;
+mod vfs_impl;
 
 use std::{
     fs::File,
-    io::{BufReader, Cursor, Read, Seek},
+    io::{BufWriter, Cursor, Read, Seek},
+    path::{Path, PathBuf},
 };
 
+use crate::cfg_impl::{Config, ConfigClass, ConfigEntry, ConfigValue};
+use crate::mods_impl::scan_mods;
 use crate::oprw_impl::create_wrp_from_buf;
 use crate::oprw_impl::create_wrp_from_vec;
+use crate::pbo_impl::{
+    normalize_entry_path, packing_method_from_mime, EntryFilter, EntrySource, PboEntryStreamCxx,
+    PboError, PboHeader, PboSource, PositionalSource, ReadSeek, SharedBuffer, WriterEntry,
+};
+use crate::preprocessor_impl::{decode_text, FsIncludeResolver, NoIncludeResolver};
+use crate::sign_impl::{
+    create_bikey_path, create_bikey_vec, create_biprivatekey_path, create_biprivatekey_vec,
+    create_bisign_path, create_bisign_vec, generate_biprivatekey, sign_pbo_vec, write_bikey_path,
+    write_bikey_vec, write_bisign_path, write_bisign_vec, BiPrivateKeyCxx,
+};
+use crate::vfs_impl::{create_vfs, VfsCxx};
 
-use bridge::{EntryCxx, LodCxx, MipmapCxx, ODOLCxx, PboCxx, ResolutionEnumCxx};
-use cxx::{CxxString, CxxVector};
-use rvff::{
-    core::read::ReadExtTrait,
-    p3d::ODOL,
-    paa::Paa,
-    pbo::PboReader,
-    rap::{Cfg, CfgClass, CfgEntry, EntryReturn},
+use bridge::{
+    BiKeyCxx, BiSignCxx, BiSignVersionCxx, CfgEntryInfoCxx, CfgEntryKindCxx, CfgValueTypeCxx,
+    EntryCxx, EntryInfoCxx, ExtractResultCxx, LodCxx, MipmapCxx, ODOLCxx, PboCxx, PboDiagnosticCxx,
+    PboDiagnosticKindCxx, PboDiffCxx, PboErrorCxx, PboErrorKindCxx, PboHashCheckCxx,
+    PboPropertyCxx, ResolutionEnumCxx,
 };
+use cxx::{CxxString, CxxVector};
+use rvff::{core::read::ReadExtTrait, p3d::ODOL, paa::Paa, pbo::PboReader};
 
 pub struct OdolLazyReaderCxx {
     reader: Cursor<Vec<u8>>,
@@ -76,63 +94,530 @@ fn create_odol_lazy_reader_internal(buf: &[u8]) -> anyhow::Result<Box<OdolLazyRe
 }
 
 pub struct PboReaderCxx {
-    reader: PboReader<BufReader<File>>,
+    source: PboSource,
+    header: PboHeader,
+    diagnostics: Vec<PboDiagnosticCxx>,
 }
 
 impl PboReaderCxx {
     pub fn get_entry(&mut self, entry_path: &CxxString) -> anyhow::Result<EntryCxx> {
-        let entry = self.reader.get_entry(&entry_path.to_string())?;
+        let entry_path = entry_path.to_string();
 
-        if let Some(entry) = entry {
-            Ok(entry.into())
+        if let Some(entry) = self.header.find_entry(&entry_path) {
+            pbo_impl::read_entry(&self.source, entry)
         } else {
-            Err(anyhow::anyhow!(format!(
-                "PBO: Entry {} not found",
-                entry_path
-            )))
+            Err(PboError::new(PboErrorKindCxx::NotFound, &entry_path, "Entry not found").into())
         }
     }
 
     pub fn has_entry(&self, entry_path: &CxxString) -> bool {
-        self.reader.pbo.has_entry(&entry_path.to_string())
+        self.header.find_entry(&entry_path.to_string()).is_some()
     }
 
     pub fn get_entry_data(&mut self, entry_path: &CxxString) -> anyhow::Result<Vec<u8>> {
-        if let Ok(entry) = self.get_entry(entry_path) {
-            Ok(entry.data)
+        Ok(self.get_entry(entry_path)?.data)
+    }
+
+    /// Properties, entries without data and the stored SHA1 checksum.
+    pub fn get_pbo(&self) -> PboCxx {
+        PboCxx {
+            properties: self
+                .header
+                .properties
+                .iter()
+                .map(|(key, value)| PboPropertyCxx {
+                    key: key.clone(),
+                    value: value.clone(),
+                })
+                .collect(),
+            entries: self
+                .header
+                .entries
+                .iter()
+                .map(|e| pbo_impl::entry_with_data(e, Vec::new()))
+                .collect(),
+            hash: self
+                .source
+                .open()
+                .ok()
+                .and_then(|mut r| pbo_impl::read_stored_hash(&mut r, &self.header).ok())
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn list_entries(&self) -> Vec<EntryInfoCxx> {
+        self.header.entries.iter().map(|e| e.into()).collect()
+    }
+
+    /// Borrows the data of an uncompressed entry from a memory or mmap backed reader.
+    pub fn get_entry_slice(&self, entry_path: &CxxString) -> anyhow::Result<&[u8]> {
+        if let Some(entry) = self.header.find_entry(&entry_path.to_string()) {
+            self.source.entry_slice(entry)
         } else {
-            Ok(Vec::new())
+            Err(PboError::new(
+                PboErrorKindCxx::NotFound,
+                &entry_path.to_string(),
+                "Entry not found",
+            )
+            .into())
         }
     }
 
-    pub fn get_pbo(&self) -> PboCxx {
-        self.reader.pbo.clone().into()
+    pub fn open_entry_stream(
+        &self,
+        entry_path: &CxxString,
+    ) -> anyhow::Result<Box<PboEntryStreamCxx>> {
+        if let Some(entry) = self.header.find_entry(&entry_path.to_string()) {
+            Ok(Box::new(PboEntryStreamCxx::new(&self.source, entry)?))
+        } else {
+            Err(PboError::new(
+                PboErrorKindCxx::NotFound,
+                &entry_path.to_string(),
+                "Entry not found",
+            )
+            .into())
+        }
     }
 
+    /// Returns `false` if the entry doesn't exist.
     pub fn extract_single_file(
         &mut self,
         entry_path: &CxxString,
         out_path: &CxxString,
         full_path: bool,
     ) -> anyhow::Result<bool> {
-        Ok(self
-            .reader
-            .extract_single_file(&entry_path.to_string(), &out_path.to_string(), full_path)
-            .is_ok())
+        let entry_path = entry_path.to_string();
+        if self.header.find_entry(&entry_path).is_none() {
+            return Ok(false);
+        }
+
+        pbo_impl::extract_single_entry(
+            &self.source,
+            &self.header,
+            &entry_path,
+            Path::new(&out_path.to_string()),
+            full_path,
+        )?;
+        Ok(true)
+    }
+
+    pub fn extract_all(
+        &self,
+        out_dir: &CxxString,
+        include_globs: &CxxVector<CxxString>,
+        exclude_globs: &CxxVector<CxxString>,
+        keep_prefix: bool,
+        threads: u32,
+    ) -> anyhow::Result<Vec<ExtractResultCxx>> {
+        let include: Vec<String> = include_globs.iter().map(|s| s.to_string()).collect();
+        let exclude: Vec<String> = exclude_globs.iter().map(|s| s.to_string()).collect();
+        let filter = EntryFilter::new(&include, &exclude)?;
+
+        pbo_impl::extract_entries(
+            &self.source,
+            &self.header,
+            &PathBuf::from(out_dir.to_string()),
+            &filter,
+            keep_prefix,
+            threads,
+        )
+    }
+
+    pub fn get_prefix(&self) -> String {
+        self.header
+            .get_property("prefix")
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    /// Anomalies found by a lenient open, always empty otherwise.
+    pub fn get_diagnostics(&self) -> Vec<PboDiagnosticCxx> {
+        self.diagnostics.clone()
+    }
+
+    /// Starts a writer holding all entries and properties of this PBO.
+    ///
+    /// Entries that aren't replaced are copied as stored, without recompressing them.
+    pub fn edit(&self) -> Box<PboWriterCxx> {
+        Box::new(PboWriterCxx {
+            properties: self.header.properties.clone(),
+            entries: self
+                .header
+                .entries
+                .iter()
+                .map(|entry| WriterEntry {
+                    filename: entry.filename.clone(),
+                    packing_method: entry.packing_method,
+                    timestamp: entry.timestamp,
+                    source: EntrySource::Raw(self.source.clone(), entry.clone()),
+                })
+                .collect(),
+        })
+    }
+
+    pub fn diff(&self, other: &PboReaderCxx) -> anyhow::Result<PboDiffCxx> {
+        pbo_impl::diff_pbos(&self.source, &self.header, &other.source, &other.header)
+    }
+
+    fn open_source(&self) -> anyhow::Result<Box<dyn ReadSeek>> {
+        self.source
+            .open()
+            .map_err(|e| PboError::wrap(PboErrorKindCxx::Io, "", e.into()).into())
+    }
+
+    pub fn verify_hash(&self) -> anyhow::Result<PboHashCheckCxx> {
+        pbo_impl::check_hash(&mut self.open_source()?)
+            .map_err(|e| PboError::wrap(PboErrorKindCxx::Truncated, "", e).into())
+    }
+
+    pub fn verify_signature(&self, key: &BiKeyCxx, sign: &BiSignCxx) -> anyhow::Result<bool> {
+        sign_impl::verify_signature(&mut self.open_source()?, key, sign)
+            .map_err(|e| PboError::wrap(PboErrorKindCxx::Truncated, "", e).into())
+    }
+
+    pub fn sign(
+        &self,
+        key: &BiPrivateKeyCxx,
+        version: BiSignVersionCxx,
+    ) -> anyhow::Result<BiSignCxx> {
+        sign_impl::sign_pbo(&mut self.open_source()?, key, version)
+            .map_err(|e| PboError::wrap(PboErrorKindCxx::Truncated, "", e).into())
+    }
+}
+
+/// PBO reader for concurrent lookups, reading entries by offset without shared cursor state.
+pub struct SharedPboReaderCxx {
+    source: PositionalSource,
+    header: PboHeader,
+}
+
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<SharedPboReaderCxx>();
+};
+
+impl SharedPboReaderCxx {
+    pub fn get_entry(&self, entry_path: &CxxString) -> anyhow::Result<EntryCxx> {
+        let entry_path = entry_path.to_string();
+
+        if let Some(entry) = self.header.find_entry(&entry_path) {
+            self.source.read_entry(entry)
+        } else {
+            Err(PboError::new(PboErrorKindCxx::NotFound, &entry_path, "Entry not found").into())
+        }
+    }
+
+    pub fn get_entry_data(&self, entry_path: &CxxString) -> anyhow::Result<Vec<u8>> {
+        Ok(self.get_entry(entry_path)?.data)
+    }
+
+    pub fn has_entry(&self, entry_path: &CxxString) -> bool {
+        self.header.find_entry(&entry_path.to_string()).is_some()
+    }
+
+    pub fn list_entries(&self) -> Vec<EntryInfoCxx> {
+        self.header.entries.iter().map(|e| e.into()).collect()
     }
 
     pub fn get_prefix(&self) -> String {
-        self.reader.get_prefix()
+        self.header
+            .get_property("prefix")
+            .unwrap_or_default()
+            .to_string()
     }
 }
 
+pub fn create_shared_pbo_reader_path(path: &CxxString) -> anyhow::Result<Box<SharedPboReaderCxx>> {
+    create_shared_pbo_reader_internal(open_pbo_file(path)?)
+}
+
+pub fn create_shared_pbo_reader_vec(buf: Vec<u8>) -> anyhow::Result<Box<SharedPboReaderCxx>> {
+    create_shared_pbo_reader_internal(PboSource::Memory(SharedBuffer::new(buf)))
+}
+
+fn create_shared_pbo_reader_internal(source: PboSource) -> anyhow::Result<Box<SharedPboReaderCxx>> {
+    let mut reader = source
+        .open()
+        .map_err(|e| PboError::wrap(PboErrorKindCxx::Io, "", e.into()))?;
+    let header = pbo_impl::read_header(&mut reader)
+        .map_err(|e| PboError::wrap(PboErrorKindCxx::InvalidHeader, "", e))?;
+
+    Ok(Box::new(SharedPboReaderCxx {
+        source: PositionalSource::new(&source)
+            .map_err(|e| PboError::wrap(PboErrorKindCxx::Io, "", e.into()))?,
+        header,
+    }))
+}
+
+fn open_pbo_file(path: &CxxString) -> anyhow::Result<PboSource> {
+    PboSource::open_file(Path::new(&path.to_string()))
+        .map_err(|e| PboError::wrap(PboErrorKindCxx::Io, "", e.into()).into())
+}
+
 pub fn create_pbo_reader_path(path: &CxxString) -> anyhow::Result<Box<PboReaderCxx>> {
-    let file = File::open(path.to_string())?;
-    let reader = BufReader::new(file);
+    create_pbo_reader_internal(open_pbo_file(path)?)
+}
+
+pub fn create_pbo_reader_vec(buf: Vec<u8>) -> anyhow::Result<Box<PboReaderCxx>> {
+    create_pbo_reader_internal(PboSource::Memory(SharedBuffer::new(buf)))
+}
+
+/// Maps the file into memory, it must not be modified while the reader is alive.
+pub fn create_pbo_reader_mmap(path: &CxxString) -> anyhow::Result<Box<PboReaderCxx>> {
+    let buf = SharedBuffer::map(Path::new(&path.to_string()))
+        .map_err(|e| PboError::wrap(PboErrorKindCxx::Io, "", e.into()))?;
+    create_pbo_reader_internal(PboSource::Memory(buf))
+}
+
+pub fn create_pbo_reader_buf(buf: &CxxVector<u8>) -> anyhow::Result<Box<PboReaderCxx>> {
+    create_pbo_reader_internal(PboSource::Memory(SharedBuffer::new(
+        buf.as_slice().to_vec(),
+    )))
+}
+
+pub fn parse_pbo_error(what: &CxxString) -> PboErrorCxx {
+    PboError::parse(&what.to_string())
+}
+
+fn create_pbo_reader_internal(source: PboSource) -> anyhow::Result<Box<PboReaderCxx>> {
+    let mut reader = source
+        .open()
+        .map_err(|e| PboError::wrap(PboErrorKindCxx::Io, "", e.into()))?;
+    let header = pbo_impl::read_header(&mut reader)
+        .map_err(|e| PboError::wrap(PboErrorKindCxx::InvalidHeader, "", e))?;
+
+    Ok(Box::new(PboReaderCxx {
+        source,
+        header,
+        diagnostics: Vec::new(),
+    }))
+}
+
+pub fn create_pbo_reader_path_lenient(path: &CxxString) -> anyhow::Result<Box<PboReaderCxx>> {
+    create_pbo_reader_lenient_internal(open_pbo_file(path)?)
+}
+
+pub fn create_pbo_reader_vec_lenient(buf: Vec<u8>) -> anyhow::Result<Box<PboReaderCxx>> {
+    create_pbo_reader_lenient_internal(PboSource::Memory(SharedBuffer::new(buf)))
+}
+
+pub fn create_pbo_reader_buf_lenient(buf: &CxxVector<u8>) -> anyhow::Result<Box<PboReaderCxx>> {
+    create_pbo_reader_lenient_internal(PboSource::Memory(SharedBuffer::new(
+        buf.as_slice().to_vec(),
+    )))
+}
+
+/// Maps the file into memory, it must not be modified while the reader is alive.
+pub fn create_pbo_reader_mmap_lenient(path: &CxxString) -> anyhow::Result<Box<PboReaderCxx>> {
+    let buf = SharedBuffer::map(Path::new(&path.to_string()))
+        .map_err(|e| PboError::wrap(PboErrorKindCxx::Io, "", e.into()))?;
+    create_pbo_reader_lenient_internal(PboSource::Memory(buf))
+}
+
+/// Opens damaged PBOs as far as possible, recording every anomaly as a diagnostic.
+fn create_pbo_reader_lenient_internal(source: PboSource) -> anyhow::Result<Box<PboReaderCxx>> {
+    let open = || {
+        source
+            .open()
+            .map_err(|e| PboError::wrap(PboErrorKindCxx::Io, "", e.into()))
+    };
+
+    let mut diagnostics = Vec::new();
+    let header = pbo_impl::read_header_lenient(&mut open()?, &mut diagnostics)?;
+    // Only reports whether rvff's reader accepts the file, reading uses the sanitized header.
+    if let Err(e) = PboReader::from_stream(open()?) {
+        diagnostics.push(PboDiagnosticCxx {
+            kind: PboDiagnosticKindCxx::ReaderFailed,
+            entry_path: String::new(),
+            message: e.to_string(),
+        });
+    }
+
+    Ok(Box::new(PboReaderCxx {
+        source,
+        header,
+        diagnostics,
+    }))
+}
+
+pub struct PboWriterCxx {
+    properties: Vec<(String, String)>,
+    entries: Vec<WriterEntry>,
+}
+
+impl PboWriterCxx {
+    pub fn set_property(&mut self, key: &CxxString, value: &CxxString) {
+        let key = key.to_string();
+        let value = value.to_string();
+
+        if let Some(property) = self.properties.iter_mut().find(|(k, _)| *k == key) {
+            property.1 = value;
+        } else {
+            self.properties.push((key, value));
+        }
+    }
+
+    pub fn add_entry_path(
+        &mut self,
+        entry_path: &CxxString,
+        path: &CxxString,
+        timestamp: u32,
+        mime_type: &CxxString,
+    ) -> anyhow::Result<()> {
+        let path = PathBuf::from(path.to_string());
+        if !path.is_file() {
+            return Err(PboError::new(
+                PboErrorKindCxx::NotFound,
+                &entry_path.to_string(),
+                format!("File {} not found", path.display()),
+            )
+            .into());
+        }
+
+        self.add_entry(entry_path, EntrySource::Path(path), timestamp, mime_type)
+    }
+
+    pub fn add_entry_vec(
+        &mut self,
+        entry_path: &CxxString,
+        data: Vec<u8>,
+        timestamp: u32,
+        mime_type: &CxxString,
+    ) -> anyhow::Result<()> {
+        self.add_entry(entry_path, EntrySource::Data(data), timestamp, mime_type)
+    }
 
-    let reader = PboReader::from_stream(reader)?;
+    fn add_entry(
+        &mut self,
+        entry_path: &CxxString,
+        source: EntrySource,
+        timestamp: u32,
+        mime_type: &CxxString,
+    ) -> anyhow::Result<()> {
+        let filename = normalize_entry_path(&entry_path.to_string());
+        if filename.is_empty() || filename.contains('\0') {
+            return Err(PboError::new(
+                PboErrorKindCxx::InvalidArgument,
+                &filename,
+                "Invalid entry path",
+            )
+            .into());
+        }
 
-    Ok(Box::new(PboReaderCxx { reader }))
+        let entry = WriterEntry {
+            filename,
+            packing_method: packing_method_from_mime(&mime_type.to_string())?,
+            timestamp,
+            source,
+        };
+
+        if let Some(existing) = self
+            .entries
+            .iter_mut()
+            .find(|e| e.filename.eq_ignore_ascii_case(&entry.filename))
+        {
+            *existing = entry;
+        } else {
+            self.entries.push(entry);
+        }
+        Ok(())
+    }
+
+    pub fn remove_property(&mut self, key: &CxxString) -> bool {
+        let key = key.to_string();
+        let len = self.properties.len();
+        self.properties.retain(|(k, _)| *k != key);
+        self.properties.len() != len
+    }
+
+    fn find_entry_index(&self, entry_path: &str) -> anyhow::Result<usize> {
+        let filename = normalize_entry_path(entry_path);
+        self.entries
+            .iter()
+            .position(|e| e.filename.eq_ignore_ascii_case(&filename))
+            .ok_or_else(|| {
+                PboError::new(PboErrorKindCxx::NotFound, &filename, "Entry not found").into()
+            })
+    }
+
+    pub fn remove_entry(&mut self, entry_path: &CxxString) -> anyhow::Result<()> {
+        let index = self.find_entry_index(&entry_path.to_string())?;
+        self.entries.remove(index);
+        Ok(())
+    }
+
+    pub fn rename_entry(
+        &mut self,
+        entry_path: &CxxString,
+        new_entry_path: &CxxString,
+    ) -> anyhow::Result<()> {
+        let index = self.find_entry_index(&entry_path.to_string())?;
+        let filename = normalize_entry_path(&new_entry_path.to_string());
+        if filename.is_empty() || filename.contains('\0') {
+            return Err(PboError::new(
+                PboErrorKindCxx::InvalidArgument,
+                &filename,
+                "Invalid entry path",
+            )
+            .into());
+        }
+        if self
+            .entries
+            .iter()
+            .enumerate()
+            .any(|(i, e)| i != index && e.filename.eq_ignore_ascii_case(&filename))
+        {
+            return Err(PboError::new(
+                PboErrorKindCxx::InvalidArgument,
+                &filename,
+                "Entry already exists",
+            )
+            .into());
+        }
+
+        self.entries[index].filename = filename;
+        Ok(())
+    }
+
+    /// Writes to a temporary file first, so a PBO can be repacked onto its own source.
+    /// Returns the SHA1 checksum of the written PBO.
+    pub fn write_to_path(&self, path: &CxxString) -> anyhow::Result<Vec<u8>> {
+        let path = PathBuf::from(path.to_string());
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+
+        let result = File::create(&tmp_path)
+            .map_err(|e| PboError::file("", Path::new(&tmp_path), e).into())
+            .and_then(|file| {
+                pbo_impl::write_pbo(BufWriter::new(file), &self.properties, &self.entries)
+                    .map_err(|e| PboError::wrap(PboErrorKindCxx::Io, "", e).into())
+            });
+        match result {
+            Ok(hash) => {
+                std::fs::rename(&tmp_path, &path).map_err(|e| PboError::file("", &path, e))?;
+                Ok(hash)
+            }
+            Err(e) => {
+                let _ = std::fs::remove_file(&tmp_path);
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the PBO bytes, including the SHA1 trailer.
+    pub fn write_to_vec(&self) -> anyhow::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        pbo_impl::write_pbo(&mut buf, &self.properties, &self.entries)
+            .map_err(|e| PboError::wrap(PboErrorKindCxx::Io, "", e))?;
+        Ok(buf)
+    }
+}
+
+pub fn create_pbo_writer() -> Box<PboWriterCxx> {
+    Box::new(PboWriterCxx {
+        properties: Vec::new(),
+        entries: Vec::new(),
+    })
 }
 
 pub fn get_mipmap_from_paa_vec(buf: &Vec<u8>, index: u32) -> anyhow::Result<MipmapCxx> {
@@ -158,22 +643,71 @@ pub fn get_mipmap_from_paa(buf: &CxxVector<u8>, index: u32) -> anyhow::Result<Mi
 }
 
 pub struct CfgCxx {
-    _cfg: Cfg,
+    config: Config,
+}
+
+fn path_strings(config_path: &CxxVector<CxxString>) -> Vec<String> {
+    config_path.iter().map(|s| s.to_string()).collect()
+}
+
+fn entry_not_found(config_path: &[String]) -> anyhow::Error {
+    anyhow::anyhow!("Entry '{}' not found!", config_path.join(" >> "))
+}
+
+fn find_property<'a>(
+    class: &'a ConfigClass,
+    config_path: &CxxVector<CxxString>,
+) -> anyhow::Result<&'a ConfigValue> {
+    let config_path = path_strings(config_path);
+    match cfg_impl::find_entry(class, &config_path) {
+        Some(ConfigEntry::Property { value, .. }) => Ok(value),
+        Some(_) => Err(anyhow::anyhow!("Not a value!")),
+        None => Err(entry_not_found(&config_path)),
+    }
+}
+
+fn numeric_array(values: &[ConfigValue]) -> anyhow::Result<Vec<f32>> {
+    values
+        .iter()
+        .map(|v| match v {
+            ConfigValue::Float(f) => Ok(*f),
+            ConfigValue::Long(l) => Ok(*l as f32),
+            _ => Err(anyhow::anyhow!("Not a numeric value!")),
+        })
+        .collect()
 }
 
 impl CfgCxx {
-    fn get_entry(&mut self, config_path: &CxxVector<CxxString>) -> Option<EntryReturn> {
-        // Rust moment
-        let config_path: Vec<String> = config_path.into_iter().map(|s| s.to_string()).collect();
-        let config_path: Vec<&str> = config_path.iter().map(|s| s.as_str()).collect();
-        self._cfg.get_entry(&config_path)
+    pub fn write_to_path(&self, path: &CxxString) -> anyhow::Result<()> {
+        std::fs::write(path.to_string(), cfg_impl::rapify(&self.config)?)?;
+        Ok(())
+    }
+
+    pub fn write_to_vec(&self) -> anyhow::Result<Vec<u8>> {
+        cfg_impl::rapify(&self.config)
+    }
+
+    /// Derapifies the config into indented `config.cpp` text.
+    pub fn to_text(&self) -> anyhow::Result<String> {
+        cfg_impl::to_text(&self.config)
+    }
+
+    /// Exports the config as JSON, see `create_cfg_json` for the import.
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(&cfg_impl::to_json(
+            &self.config,
+        )?)?)
+    }
+
+    /// Exports the config as YAML, with the same layout as `to_json`.
+    pub fn to_yaml(&self) -> anyhow::Result<String> {
+        Ok(serde_norway::to_string(&cfg_impl::to_json(&self.config)?)?)
     }
 
     pub fn get_entry_as_string(&mut self, config_path: &CxxVector<CxxString>) -> String {
-        if let Some(entry) = self.get_entry(config_path) {
-            entry.as_string().unwrap_or_default()
-        } else {
-            String::new()
+        match find_property(&self.config.root, config_path) {
+            Ok(ConfigValue::String(s)) => s.clone(),
+            _ => String::new(),
         }
     }
 
@@ -181,21 +715,9 @@ impl CfgCxx {
         &mut self,
         config_path: &CxxVector<CxxString>,
     ) -> anyhow::Result<i32> {
-        if let Some(entry) = self.get_entry(config_path) {
-            if let Some(val) = entry.as_long() {
-                Ok(val)
-            } else {
-                Err(anyhow::anyhow!("Not a long value!"))
-            }
-        } else {
-            Err(anyhow::Error::msg(format!(
-                "Entry '{}' not found!",
-                config_path
-                    .iter()
-                    .map(|x| x.to_str().unwrap_or_default())
-                    .collect::<Vec<&str>>()
-                    .join(" >> ")
-            )))
+        match find_property(&self.config.root, config_path)? {
+            ConfigValue::Long(l) => Ok(*l),
+            _ => Err(anyhow::anyhow!("Not a long value!")),
         }
     }
 
@@ -203,125 +725,497 @@ impl CfgCxx {
         self: &mut CfgCxx,
         config_path: &CxxVector<CxxString>,
     ) -> anyhow::Result<Vec<f32>> {
-        if let Some(entry) = self.get_entry(config_path) {
-            if let Some(val) = entry.as_array() {
-                let mut arr = Vec::new();
-                for v in val {
-                    match v {
-                        rvff::rap::CfgValue::Float(f) => arr.push(f),
-                        rvff::rap::CfgValue::Long(l) => arr.push(l as f32),
-                        _ => return Err(anyhow::anyhow!("Not a numeric value!")),
-                    };
-                }
-                Ok(arr)
-            } else {
-                Err(anyhow::anyhow!("Not an array!"))
-            }
-        } else {
-            Err(anyhow::Error::msg(format!(
-                "Entry '{}' not found!",
-                config_path
-                    .iter()
-                    .map(|x| x.to_str().unwrap_or_default())
-                    .collect::<Vec<&str>>()
-                    .join(" >> ")
-            )))
+        match find_property(&self.config.root, config_path)? {
+            ConfigValue::Array(values) => numeric_array(values),
+            _ => Err(anyhow::anyhow!("Not an array!")),
         }
     }
 
     pub fn get_entry_as_entries(&mut self, config_path: &CxxVector<CxxString>) -> Vec<CfgEntryCxx> {
-        if let Some(entry) = self.get_entry(config_path) {
-            if let Some(class) = entry.as_class() {
-                return class.entries.into_iter().map(|e| e.into()).collect();
-            }
+        match cfg_impl::find_entry(&self.config.root, &path_strings(config_path)) {
+            Some(ConfigEntry::Class(class)) => class
+                .entries
+                .iter()
+                .map(|entry| CfgEntryCxx {
+                    entry: entry.clone(),
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn get_inherited(
+        &self,
+        config_path: &CxxVector<CxxString>,
+    ) -> anyhow::Result<(&ConfigEntry, Vec<String>)> {
+        let config_path = path_strings(config_path);
+        cfg_impl::find_inherited(&self.config.root, &config_path)
+            .ok_or_else(|| entry_not_found(&config_path))
+    }
+
+    fn get_inherited_value(
+        &self,
+        config_path: &CxxVector<CxxString>,
+    ) -> anyhow::Result<&ConfigValue> {
+        match self.get_inherited(config_path)?.0 {
+            ConfigEntry::Property { value, .. } => Ok(value),
+            _ => Err(anyhow::anyhow!("Not a value!")),
+        }
+    }
+
+    /// Like `get_entry_as_string`, but follows `class X: Y` inheritance.
+    pub fn get_inherited_as_string(&self, config_path: &CxxVector<CxxString>) -> String {
+        match self.get_inherited_value(config_path) {
+            Ok(ConfigValue::String(s)) | Ok(ConfigValue::Expression(s)) => s.clone(),
+            _ => String::new(),
         }
-        Vec::new()
+    }
+
+    pub fn get_inherited_as_number(
+        &self,
+        config_path: &CxxVector<CxxString>,
+    ) -> anyhow::Result<i32> {
+        match self.get_inherited_value(config_path)? {
+            ConfigValue::Long(l) => Ok(*l),
+            _ => Err(anyhow::anyhow!("Not a long value!")),
+        }
+    }
+
+    pub fn get_inherited_as_array_float(
+        &self,
+        config_path: &CxxVector<CxxString>,
+    ) -> anyhow::Result<Vec<f32>> {
+        match self.get_inherited_value(config_path)? {
+            ConfigValue::Array(values) => numeric_array(values),
+            _ => Err(anyhow::anyhow!("Not an array!")),
+        }
+    }
+
+    /// Returns the path of the class that defines an (possibly inherited) entry.
+    pub fn get_defining_class(
+        &self,
+        config_path: &CxxVector<CxxString>,
+    ) -> anyhow::Result<Vec<String>> {
+        Ok(self.get_inherited(config_path)?.1)
+    }
+
+    pub fn get_value(
+        &self,
+        config_path: &CxxVector<CxxString>,
+    ) -> anyhow::Result<Box<CfgValueCxx>> {
+        CfgValueCxx::from_entry(find_typed_entry(&self.config.root, config_path)?)
+    }
+
+    pub fn get_type(&self, config_path: &CxxVector<CxxString>) -> anyhow::Result<CfgValueTypeCxx> {
+        entry_type(find_typed_entry(&self.config.root, config_path)?)
+    }
+
+    fn get_class<T>(
+        &self,
+        config_path: &CxxVector<CxxString>,
+        f: impl FnOnce(&ConfigClass) -> T,
+    ) -> anyhow::Result<T> {
+        let config_path = path_strings(config_path);
+        cfg_impl::find_class(&self.config.root, &config_path)
+            .map(f)
+            .ok_or_else(|| anyhow::anyhow!("Class '{}' not found!", config_path.join(" >> ")))
+    }
+
+    pub fn get_parent_name(&self, config_path: &CxxVector<CxxString>) -> anyhow::Result<String> {
+        self.get_class(config_path, |c| c.parent.clone())
+    }
+
+    /// Lists the direct entries of a class, an empty path lists the root.
+    pub fn get_entries(
+        &self,
+        config_path: &CxxVector<CxxString>,
+    ) -> anyhow::Result<Vec<CfgEntryInfoCxx>> {
+        self.get_class(config_path, class_entries)
+    }
+
+    /// Lists all entries below a class depth-first, paths are relative to that class.
+    pub fn walk_entries(
+        &self,
+        config_path: &CxxVector<CxxString>,
+    ) -> anyhow::Result<Vec<CfgEntryInfoCxx>> {
+        self.get_class(config_path, walk_class)
     }
 }
 
 pub fn create_cfg_path(path: &CxxString) -> anyhow::Result<Box<CfgCxx>> {
-    let file = File::open(path.to_string())?;
-    let mut reader = BufReader::new(file);
+    let buf = std::fs::read(path.to_string())?;
 
-    let cfg = Cfg::read(&mut reader)?;
+    let config = cfg_impl::read_rapified(&buf)?;
 
-    Ok(Box::new(CfgCxx { _cfg: cfg }))
+    Ok(Box::new(CfgCxx { config }))
 }
 
 pub fn create_cfg_vec(buf: &Vec<u8>) -> anyhow::Result<Box<CfgCxx>> {
-    let mut reader = Cursor::new(buf);
+    let config = cfg_impl::read_rapified(buf)?;
+
+    Ok(Box::new(CfgCxx { config }))
+}
+
+/// Parses a text config, includes are resolved relative to the including file.
+///
+/// `\`-rooted includes are rejected, see `create_cfg_text_path_root`.
+pub fn create_cfg_text_path(path: &CxxString) -> anyhow::Result<Box<CfgCxx>> {
+    create_cfg_text_file(&path.to_string(), None)
+}
+
+/// Like `create_cfg_text_path`, `\`-rooted includes are resolved relative to `root`.
+pub fn create_cfg_text_path_root(
+    path: &CxxString,
+    root: &CxxString,
+) -> anyhow::Result<Box<CfgCxx>> {
+    create_cfg_text_file(&path.to_string(), Some(PathBuf::from(root.to_string())))
+}
 
-    let cfg = Cfg::read(&mut reader)?;
+fn create_cfg_text_file(path: &str, root: Option<PathBuf>) -> anyhow::Result<Box<CfgCxx>> {
+    let content = decode_text(std::fs::read(path)?);
+    let config = cfg_impl::parse_text(path, &content, &FsIncludeResolver { root })?;
+    Ok(Box::new(CfgCxx { config }))
+}
+
+/// Parses a text config from the VFS, `\`-rooted includes are game paths.
+pub fn create_cfg_text_vfs(vfs: &VfsCxx, path: &CxxString) -> anyhow::Result<Box<CfgCxx>> {
+    let path = path.to_string();
+    let content = decode_text(vfs.read_path(&path)?);
+    let config = cfg_impl::parse_text(&path, &content, vfs)?;
+    Ok(Box::new(CfgCxx { config }))
+}
+
+/// Parses a text config stored in the PBO, includes are resolved to entries of the same PBO.
+pub fn create_cfg_text_pbo(
+    pbo: &PboReaderCxx,
+    entry_path: &CxxString,
+) -> anyhow::Result<Box<CfgCxx>> {
+    let entry_path = entry_path.to_string();
+    let entry = pbo
+        .header
+        .find_entry(&entry_path)
+        .ok_or_else(|| PboError::new(PboErrorKindCxx::NotFound, &entry_path, "Entry not found"))?;
+    let data = pbo_impl::read_entry_data(&mut pbo.source.open()?, entry)?;
+    let prefix = pbo.header.get_property("prefix").unwrap_or_default();
+    let file = match prefix.trim_matches('\\') {
+        "" => entry.filename.clone(),
+        prefix => format!("{}\\{}", prefix, entry.filename),
+    };
+    let config = cfg_impl::parse_text(&file, &decode_text(data), pbo)?;
+    Ok(Box::new(CfgCxx { config }))
+}
+
+/// Parses a text config without include support.
+pub fn create_cfg_text_vec(buf: &Vec<u8>) -> anyhow::Result<Box<CfgCxx>> {
+    let content = decode_text(buf.clone());
+    let config = cfg_impl::parse_text("config.cpp", &content, &NoIncludeResolver)?;
+    Ok(Box::new(CfgCxx { config }))
+}
+
+pub fn create_cfg_json(json: &CxxString) -> anyhow::Result<Box<CfgCxx>> {
+    let value: serde_json::Value = serde_json::from_slice(json.as_bytes())?;
+    Ok(Box::new(CfgCxx {
+        config: cfg_impl::from_json(&value)?,
+    }))
+}
+
+pub fn create_cfg_yaml(yaml: &CxxString) -> anyhow::Result<Box<CfgCxx>> {
+    let value: serde_json::Value = serde_norway::from_slice(yaml.as_bytes())?;
+    Ok(Box::new(CfgCxx {
+        config: cfg_impl::from_json(&value)?,
+    }))
+}
+
+/// Typed config value, arrays keep their nesting.
+#[derive(Debug)]
+pub struct CfgValueCxx {
+    value: TypedValue,
+}
+
+/// A class is only kept by name, its entries are read through the config.
+#[derive(Debug)]
+enum TypedValue {
+    Value(ConfigValue),
+    Class(String),
+    None,
+}
+
+fn value_type(value: &ConfigValue) -> CfgValueTypeCxx {
+    match value {
+        ConfigValue::String(_) => CfgValueTypeCxx::String,
+        ConfigValue::Float(_) => CfgValueTypeCxx::Float,
+        ConfigValue::Long(_) => CfgValueTypeCxx::Long,
+        ConfigValue::Int64(_) => CfgValueTypeCxx::Int64,
+        ConfigValue::Expression(_) => CfgValueTypeCxx::Expression,
+        ConfigValue::Array(_) => CfgValueTypeCxx::Array,
+    }
+}
+
+fn entry_type(entry: &ConfigEntry) -> anyhow::Result<CfgValueTypeCxx> {
+    match entry {
+        ConfigEntry::Property { value, .. } => Ok(value_type(value)),
+        ConfigEntry::Class(_) => Ok(CfgValueTypeCxx::Class),
+        _ => Err(anyhow::anyhow!("Not a value or class!")),
+    }
+}
+
+impl CfgValueCxx {
+    /// Like `from_entry`, but `class X;` and `delete X;` have the `None` type.
+    fn from_any_entry(entry: &ConfigEntry) -> Box<Self> {
+        let value = match entry {
+            ConfigEntry::Property { value, .. } => TypedValue::Value(value.clone()),
+            ConfigEntry::Class(class) => TypedValue::Class(class.name.clone()),
+            _ => TypedValue::None,
+        };
+        Box::new(Self { value })
+    }
+
+    fn from_entry(entry: &ConfigEntry) -> anyhow::Result<Box<Self>> {
+        entry_type(entry)?;
+        Ok(Self::from_any_entry(entry))
+    }
+
+    pub fn get_type(&self) -> CfgValueTypeCxx {
+        match &self.value {
+            TypedValue::Value(value) => value_type(value),
+            TypedValue::Class(_) => CfgValueTypeCxx::Class,
+            TypedValue::None => CfgValueTypeCxx::None,
+        }
+    }
+
+    /// Returns string and expression values.
+    pub fn as_string(&self) -> anyhow::Result<String> {
+        match &self.value {
+            TypedValue::Value(ConfigValue::String(s) | ConfigValue::Expression(s)) => Ok(s.clone()),
+            _ => Err(anyhow::anyhow!("Not a string value!")),
+        }
+    }
+
+    /// Returns float values, longs are converted.
+    pub fn as_float(&self) -> anyhow::Result<f32> {
+        match &self.value {
+            TypedValue::Value(ConfigValue::Float(f)) => Ok(*f),
+            TypedValue::Value(ConfigValue::Long(l)) => Ok(*l as f32),
+            _ => Err(anyhow::anyhow!("Not a numeric value!")),
+        }
+    }
+
+    pub fn as_long(&self) -> anyhow::Result<i32> {
+        match &self.value {
+            TypedValue::Value(ConfigValue::Long(l)) => Ok(*l),
+            _ => Err(anyhow::anyhow!("Not a long value!")),
+        }
+    }
+
+    /// Returns int64 values, longs are converted.
+    pub fn as_int64(&self) -> anyhow::Result<i64> {
+        match &self.value {
+            TypedValue::Value(ConfigValue::Int64(l)) => Ok(*l),
+            TypedValue::Value(ConfigValue::Long(l)) => Ok(i64::from(*l)),
+            _ => Err(anyhow::anyhow!("Not an int64 value!")),
+        }
+    }
+
+    fn as_array(&self) -> anyhow::Result<&Vec<ConfigValue>> {
+        match &self.value {
+            TypedValue::Value(ConfigValue::Array(values)) => Ok(values),
+            _ => Err(anyhow::anyhow!("Not an array!")),
+        }
+    }
+
+    pub fn get_array_len(&self) -> anyhow::Result<usize> {
+        Ok(self.as_array()?.len())
+    }
+
+    pub fn get_array_item(&self, index: usize) -> anyhow::Result<Box<CfgValueCxx>> {
+        let value = self
+            .as_array()?
+            .get(index)
+            .ok_or_else(|| anyhow::anyhow!("Array index {} out of range!", index))?;
+        Ok(Box::new(CfgValueCxx {
+            value: TypedValue::Value(value.clone()),
+        }))
+    }
+
+    pub fn as_string_array(&self) -> anyhow::Result<Vec<String>> {
+        self.as_array()?
+            .iter()
+            .map(|v| match v {
+                ConfigValue::String(s) | ConfigValue::Expression(s) => Ok(s.clone()),
+                _ => Err(anyhow::anyhow!("Not a string value!")),
+            })
+            .collect()
+    }
 
-    Ok(Box::new(CfgCxx { _cfg: cfg }))
+    pub fn get_class_name(&self) -> anyhow::Result<String> {
+        match &self.value {
+            TypedValue::Class(name) => Ok(name.clone()),
+            _ => Err(anyhow::anyhow!("Not a class!")),
+        }
+    }
+}
+
+fn find_typed_entry<'a>(
+    class: &'a ConfigClass,
+    config_path: &CxxVector<CxxString>,
+) -> anyhow::Result<&'a ConfigEntry> {
+    let config_path = path_strings(config_path);
+    cfg_impl::find_entry(class, &config_path).ok_or_else(|| entry_not_found(&config_path))
+}
+
+/// Merges addon configs in load order into the config the game sees.
+pub struct ConfigMergerCxx {
+    config: Config,
+}
+
+impl ConfigMergerCxx {
+    /// Applies a config on top of the ones added before it.
+    pub fn add_config(&mut self, cfg: &CfgCxx) {
+        cfg_impl::merge_config(&mut self.config, &cfg.config);
+    }
+
+    pub fn merge(&self) -> anyhow::Result<Box<CfgCxx>> {
+        Ok(Box::new(CfgCxx {
+            config: self.config.clone(),
+        }))
+    }
+}
+
+pub fn create_config_merger() -> Box<ConfigMergerCxx> {
+    Box::new(ConfigMergerCxx {
+        config: Config::default(),
+    })
 }
 
 pub struct CfgEntryCxx {
-    entry: CfgEntry,
+    entry: ConfigEntry,
 }
 
 impl CfgEntryCxx {
     pub fn get_entry_as_class(&mut self) -> anyhow::Result<Box<CfgClassCxx>> {
-        if let Some(class) = self.entry.as_class() {
-            Ok(Box::new(class.into()))
+        if let ConfigEntry::Class(class) = &self.entry {
+            Ok(Box::new(CfgClassCxx {
+                class: class.clone(),
+            }))
         } else {
             Err(anyhow::anyhow!("Not a class"))
         }
     }
+
+    pub fn get_name(&self) -> String {
+        self.entry.name().to_string()
+    }
+
+    pub fn get_kind(&self) -> CfgEntryKindCxx {
+        entry_kind(&self.entry)
+    }
+
+    /// Returns the parent class name, empty for entries that aren't classes.
+    pub fn get_parent_name(&self) -> String {
+        match &self.entry {
+            ConfigEntry::Class(class) => class.parent.clone(),
+            _ => String::new(),
+        }
+    }
+
+    pub fn get_value(&self) -> anyhow::Result<Box<CfgValueCxx>> {
+        CfgValueCxx::from_entry(&self.entry)
+    }
+}
+
+fn entry_kind(entry: &ConfigEntry) -> CfgEntryKindCxx {
+    match entry {
+        ConfigEntry::Property { .. } => CfgEntryKindCxx::Property,
+        ConfigEntry::Class(_) => CfgEntryKindCxx::Class,
+        ConfigEntry::ExternClass(_) => CfgEntryKindCxx::ExternClass,
+        ConfigEntry::DeleteClass(_) => CfgEntryKindCxx::DeleteClass,
+    }
 }
 
-impl From<CfgEntry> for CfgEntryCxx {
-    fn from(entry: CfgEntry) -> Self {
-        Self { entry }
+fn entry_info(entry: &ConfigEntry, path: Vec<String>, depth: u32) -> CfgEntryInfoCxx {
+    CfgEntryInfoCxx {
+        name: entry.name().to_string(),
+        kind: entry_kind(entry),
+        parent: match entry {
+            ConfigEntry::Class(class) => class.parent.clone(),
+            _ => String::new(),
+        },
+        path,
+        depth,
+        value: CfgValueCxx::from_any_entry(entry),
     }
 }
 
+fn class_entries(class: &ConfigClass) -> Vec<CfgEntryInfoCxx> {
+    class
+        .entries
+        .iter()
+        .map(|e| entry_info(e, vec![e.name().to_string()], 0))
+        .collect()
+}
+
+/// Lists every entry below `class` depth-first, paths are relative to `class`.
+fn walk_class(class: &ConfigClass) -> Vec<CfgEntryInfoCxx> {
+    let mut entries = Vec::new();
+    cfg_impl::visit_entries(class, &mut Vec::new(), &mut |path, entry| {
+        entries.push(entry_info(entry, path.to_vec(), path.len() as u32 - 1));
+    });
+    entries
+}
+
 pub struct CfgClassCxx {
-    class: CfgClass,
+    class: ConfigClass,
 }
 
 impl CfgClassCxx {
-    fn get_entry(&mut self, config_path: &CxxVector<CxxString>) -> Option<EntryReturn> {
-        // Rust moment
-        let config_path: Vec<String> = config_path.into_iter().map(|s| s.to_string()).collect();
-        let config_path: Vec<&str> = config_path.iter().map(|s| s.as_str()).collect();
-        self.class.get_entry(&config_path)
-    }
-
     pub fn get_entry_as_string(&mut self, config_path: &CxxVector<CxxString>) -> String {
-        if let Some(entry) = self.get_entry(config_path) {
-            entry.as_string().unwrap_or_default()
-        } else {
-            String::new()
+        match find_property(&self.class, config_path) {
+            Ok(ConfigValue::String(s)) => s.clone(),
+            _ => String::new(),
         }
     }
 
+    /// Returns 0 for missing and non-numeric entries, see `get_entry_as_float`.
     pub fn get_entry_as_number(&mut self, config_path: &CxxVector<CxxString>) -> f32 {
-        if let Some(entry) = self.get_entry(config_path) {
-            entry
-                .as_float()
-                .or_else(|| Some(entry.as_long().unwrap_or_default() as f32))
-                .unwrap_or_default()
-            // return match entry {
-            //     EntryReturn::Entry(e) => 0.0,
-            //     EntryReturn::Value(v) => match v {
-            //         CfgValue::Float(f) => f,
-            //         CfgValue::Long(l) => l as f32,
-            //         _ => 0.0,
-            //     },
-            // };
-        } else {
-            0.0
+        self.get_entry_as_float(config_path).unwrap_or_default()
+    }
+
+    /// Returns float values, longs are converted.
+    pub fn get_entry_as_float(&self, config_path: &CxxVector<CxxString>) -> anyhow::Result<f32> {
+        match find_property(&self.class, config_path)? {
+            ConfigValue::Float(f) => Ok(*f),
+            ConfigValue::Long(l) => Ok(*l as f32),
+            _ => Err(anyhow::anyhow!("Not a numeric value!")),
         }
     }
 
     pub fn get_class_name(&self) -> String {
         self.class.name.clone()
     }
-}
-impl From<CfgClass> for CfgClassCxx {
-    fn from(class: CfgClass) -> Self {
-        Self { class }
+
+    pub fn get_value(
+        &self,
+        config_path: &CxxVector<CxxString>,
+    ) -> anyhow::Result<Box<CfgValueCxx>> {
+        CfgValueCxx::from_entry(find_typed_entry(&self.class, config_path)?)
+    }
+
+    pub fn get_type(&self, config_path: &CxxVector<CxxString>) -> anyhow::Result<CfgValueTypeCxx> {
+        entry_type(find_typed_entry(&self.class, config_path)?)
+    }
+
+    pub fn get_parent_name(&self) -> String {
+        self.class.parent.clone()
+    }
+
+    /// Lists the direct entries of the class.
+    pub fn get_entries(&self) -> Vec<CfgEntryInfoCxx> {
+        class_entries(&self.class)
+    }
+
+    /// Lists all entries depth-first, each class is followed by its own entries.
+    pub fn walk_entries(&self) -> Vec<CfgEntryInfoCxx> {
+        walk_class(&self.class)
     }
 }
 
@@ -347,6 +1241,13 @@ mod bridge {
 
         fn create_cfg_path(path: &CxxString) -> Result<Box<CfgCxx>>;
         fn create_cfg_vec(buf: &Vec<u8>) -> Result<Box<CfgCxx>>;
+        fn create_cfg_text_path(path: &CxxString) -> Result<Box<CfgCxx>>;
+        fn create_cfg_text_path_root(path: &CxxString, root: &CxxString) -> Result<Box<CfgCxx>>;
+        fn create_cfg_text_vfs(vfs: &VfsCxx, path: &CxxString) -> Result<Box<CfgCxx>>;
+        fn create_cfg_text_pbo(pbo: &PboReaderCxx, entry_path: &CxxString) -> Result<Box<CfgCxx>>;
+        fn create_cfg_text_vec(buf: &Vec<u8>) -> Result<Box<CfgCxx>>;
+        fn create_cfg_json(json: &CxxString) -> Result<Box<CfgCxx>>;
+        fn create_cfg_yaml(yaml: &CxxString) -> Result<Box<CfgCxx>>;
         fn get_entry_as_string(self: &mut CfgCxx, config_path: &CxxVector<CxxString>) -> String;
         fn get_entry_as_number(
             self: &mut CfgCxx,
@@ -360,6 +1261,54 @@ mod bridge {
             self: &mut CfgCxx,
             config_path: &CxxVector<CxxString>,
         ) -> Vec<CfgEntryCxx>;
+        fn write_to_path(self: &CfgCxx, path: &CxxString) -> Result<()>;
+        fn write_to_vec(self: &CfgCxx) -> Result<Vec<u8>>;
+        fn to_text(self: &CfgCxx) -> Result<String>;
+        fn to_json(self: &CfgCxx) -> Result<String>;
+        fn to_yaml(self: &CfgCxx) -> Result<String>;
+        fn get_inherited_as_string(self: &CfgCxx, config_path: &CxxVector<CxxString>) -> String;
+        fn get_inherited_as_number(
+            self: &CfgCxx,
+            config_path: &CxxVector<CxxString>,
+        ) -> Result<i32>;
+        fn get_inherited_as_array_float(
+            self: &CfgCxx,
+            config_path: &CxxVector<CxxString>,
+        ) -> Result<Vec<f32>>;
+        fn get_defining_class(
+            self: &CfgCxx,
+            config_path: &CxxVector<CxxString>,
+        ) -> Result<Vec<String>>;
+
+        fn get_value(self: &CfgCxx, config_path: &CxxVector<CxxString>)
+            -> Result<Box<CfgValueCxx>>;
+        fn get_type(self: &CfgCxx, config_path: &CxxVector<CxxString>) -> Result<CfgValueTypeCxx>;
+
+        fn get_parent_name(self: &CfgCxx, config_path: &CxxVector<CxxString>) -> Result<String>;
+        fn get_entries(
+            self: &CfgCxx,
+            config_path: &CxxVector<CxxString>,
+        ) -> Result<Vec<CfgEntryInfoCxx>>;
+        fn walk_entries(
+            self: &CfgCxx,
+            config_path: &CxxVector<CxxString>,
+        ) -> Result<Vec<CfgEntryInfoCxx>>;
+
+        type CfgValueCxx;
+        fn get_type(self: &CfgValueCxx) -> CfgValueTypeCxx;
+        fn as_string(self: &CfgValueCxx) -> Result<String>;
+        fn as_float(self: &CfgValueCxx) -> Result<f32>;
+        fn as_long(self: &CfgValueCxx) -> Result<i32>;
+        fn as_int64(self: &CfgValueCxx) -> Result<i64>;
+        fn get_array_len(self: &CfgValueCxx) -> Result<usize>;
+        fn get_array_item(self: &CfgValueCxx, index: usize) -> Result<Box<CfgValueCxx>>;
+        fn as_string_array(self: &CfgValueCxx) -> Result<Vec<String>>;
+        fn get_class_name(self: &CfgValueCxx) -> Result<String>;
+
+        type ConfigMergerCxx;
+        fn create_config_merger() -> Box<ConfigMergerCxx>;
+        fn add_config(self: &mut ConfigMergerCxx, cfg: &CfgCxx);
+        fn merge(self: &ConfigMergerCxx) -> Result<Box<CfgCxx>>;
 
         // WRP
         fn create_wrp_from_buf(buf: &CxxVector<u8>) -> Result<OprwCxx>;
@@ -367,6 +1316,10 @@ mod bridge {
 
         type CfgEntryCxx;
         fn get_entry_as_class(self: &mut CfgEntryCxx) -> Result<Box<CfgClassCxx>>;
+        fn get_name(self: &CfgEntryCxx) -> String;
+        fn get_kind(self: &CfgEntryCxx) -> CfgEntryKindCxx;
+        fn get_parent_name(self: &CfgEntryCxx) -> String;
+        fn get_value(self: &CfgEntryCxx) -> Result<Box<CfgValueCxx>>;
 
         type CfgClassCxx;
         fn get_entry_as_string(
@@ -374,7 +1327,22 @@ mod bridge {
             config_path: &CxxVector<CxxString>,
         ) -> String;
         fn get_entry_as_number(self: &mut CfgClassCxx, config_path: &CxxVector<CxxString>) -> f32;
+        fn get_entry_as_float(
+            self: &CfgClassCxx,
+            config_path: &CxxVector<CxxString>,
+        ) -> Result<f32>;
         fn get_class_name(self: &CfgClassCxx) -> String;
+        fn get_value(
+            self: &CfgClassCxx,
+            config_path: &CxxVector<CxxString>,
+        ) -> Result<Box<CfgValueCxx>>;
+        fn get_type(
+            self: &CfgClassCxx,
+            config_path: &CxxVector<CxxString>,
+        ) -> Result<CfgValueTypeCxx>;
+        fn get_parent_name(self: &CfgClassCxx) -> String;
+        fn get_entries(self: &CfgClassCxx) -> Vec<CfgEntryInfoCxx>;
+        fn walk_entries(self: &CfgClassCxx) -> Vec<CfgEntryInfoCxx>;
 
         /// P3D
         type OdolLazyReaderCxx;
@@ -390,10 +1358,28 @@ mod bridge {
         type PboReaderCxx;
 
         unsafe fn create_pbo_reader_path(buf: &CxxString) -> Result<Box<PboReaderCxx>>;
+        fn create_pbo_reader_vec(buf: Vec<u8>) -> Result<Box<PboReaderCxx>>;
+        fn create_pbo_reader_buf(buf: &CxxVector<u8>) -> Result<Box<PboReaderCxx>>;
+        fn create_pbo_reader_mmap(path: &CxxString) -> Result<Box<PboReaderCxx>>;
+        fn create_pbo_reader_path_lenient(path: &CxxString) -> Result<Box<PboReaderCxx>>;
+        fn create_pbo_reader_vec_lenient(buf: Vec<u8>) -> Result<Box<PboReaderCxx>>;
+        fn create_pbo_reader_buf_lenient(buf: &CxxVector<u8>) -> Result<Box<PboReaderCxx>>;
+        fn create_pbo_reader_mmap_lenient(path: &CxxString) -> Result<Box<PboReaderCxx>>;
+        /// Splits a `PBO#<code> [<Kind>] <<entry>>: <message>` error message, see `PboErrorKindCxx`.
+        fn parse_pbo_error(what: &CxxString) -> PboErrorCxx;
 
         fn get_entry(self: &mut PboReaderCxx, entry_path: &CxxString) -> Result<EntryCxx>;
         fn get_entry_data(self: &mut PboReaderCxx, entry_path: &CxxString) -> Result<Vec<u8>>;
+        unsafe fn get_entry_slice<'a>(
+            self: &'a PboReaderCxx,
+            entry_path: &CxxString,
+        ) -> Result<&'a [u8]>;
         fn get_pbo(self: &PboReaderCxx) -> PboCxx;
+        fn list_entries(self: &PboReaderCxx) -> Vec<EntryInfoCxx>;
+        fn open_entry_stream(
+            self: &PboReaderCxx,
+            entry_path: &CxxString,
+        ) -> Result<Box<PboEntryStreamCxx>>;
         fn has_entry(self: &PboReaderCxx, entry_path: &CxxString) -> bool;
         pub fn extract_single_file(
             self: &mut PboReaderCxx,
@@ -401,7 +1387,115 @@ mod bridge {
             out_path: &CxxString,
             full_path: bool,
         ) -> Result<bool>;
+        fn extract_all(
+            self: &PboReaderCxx,
+            out_dir: &CxxString,
+            include_globs: &CxxVector<CxxString>,
+            exclude_globs: &CxxVector<CxxString>,
+            keep_prefix: bool,
+            threads: u32,
+        ) -> Result<Vec<ExtractResultCxx>>;
         pub fn get_prefix(self: &PboReaderCxx) -> String;
+        fn edit(self: &PboReaderCxx) -> Box<PboWriterCxx>;
+        fn diff(self: &PboReaderCxx, other: &PboReaderCxx) -> Result<PboDiffCxx>;
+        fn verify_hash(self: &PboReaderCxx) -> Result<PboHashCheckCxx>;
+        fn get_diagnostics(self: &PboReaderCxx) -> Vec<PboDiagnosticCxx>;
+        fn verify_signature(self: &PboReaderCxx, key: &BiKeyCxx, sign: &BiSignCxx) -> Result<bool>;
+        fn sign(
+            self: &PboReaderCxx,
+            key: &BiPrivateKeyCxx,
+            version: BiSignVersionCxx,
+        ) -> Result<BiSignCxx>;
+
+        type SharedPboReaderCxx;
+
+        fn create_shared_pbo_reader_path(path: &CxxString) -> Result<Box<SharedPboReaderCxx>>;
+        fn create_shared_pbo_reader_vec(buf: Vec<u8>) -> Result<Box<SharedPboReaderCxx>>;
+        fn get_entry(self: &SharedPboReaderCxx, entry_path: &CxxString) -> Result<EntryCxx>;
+        fn get_entry_data(self: &SharedPboReaderCxx, entry_path: &CxxString) -> Result<Vec<u8>>;
+        fn has_entry(self: &SharedPboReaderCxx, entry_path: &CxxString) -> bool;
+        fn list_entries(self: &SharedPboReaderCxx) -> Vec<EntryInfoCxx>;
+        fn get_prefix(self: &SharedPboReaderCxx) -> String;
+
+        type PboEntryStreamCxx;
+
+        fn read(self: &mut PboEntryStreamCxx, buf: &mut [u8]) -> Result<usize>;
+        fn seek(self: &mut PboEntryStreamCxx, offset: i64, origin: SeekOriginCxx) -> Result<u64>;
+        fn size(self: &PboEntryStreamCxx) -> u64;
+        fn tell(self: &PboEntryStreamCxx) -> u64;
+
+        type PboWriterCxx;
+
+        fn create_pbo_writer() -> Box<PboWriterCxx>;
+
+        fn set_property(self: &mut PboWriterCxx, key: &CxxString, value: &CxxString);
+        fn add_entry_path(
+            self: &mut PboWriterCxx,
+            entry_path: &CxxString,
+            path: &CxxString,
+            timestamp: u32,
+            mime_type: &CxxString,
+        ) -> Result<()>;
+        fn add_entry_vec(
+            self: &mut PboWriterCxx,
+            entry_path: &CxxString,
+            data: Vec<u8>,
+            timestamp: u32,
+            mime_type: &CxxString,
+        ) -> Result<()>;
+        fn remove_property(self: &mut PboWriterCxx, key: &CxxString) -> bool;
+        fn remove_entry(self: &mut PboWriterCxx, entry_path: &CxxString) -> Result<()>;
+        fn rename_entry(
+            self: &mut PboWriterCxx,
+            entry_path: &CxxString,
+            new_entry_path: &CxxString,
+        ) -> Result<()>;
+        /// Returns the SHA1 checksum of the written PBO.
+        fn write_to_path(self: &PboWriterCxx, path: &CxxString) -> Result<Vec<u8>>;
+        /// Returns the PBO bytes.
+        fn write_to_vec(self: &PboWriterCxx) -> Result<Vec<u8>>;
+
+        // Signatures
+        fn create_bikey_path(path: &CxxString) -> Result<BiKeyCxx>;
+        fn create_bikey_vec(buf: &Vec<u8>) -> Result<BiKeyCxx>;
+        fn create_bisign_path(path: &CxxString) -> Result<BiSignCxx>;
+        fn create_bisign_vec(buf: &Vec<u8>) -> Result<BiSignCxx>;
+        fn write_bikey_path(key: &BiKeyCxx, path: &CxxString) -> Result<()>;
+        fn write_bikey_vec(key: &BiKeyCxx) -> Vec<u8>;
+        fn write_bisign_path(sign: &BiSignCxx, path: &CxxString) -> Result<()>;
+        fn write_bisign_vec(sign: &BiSignCxx) -> Vec<u8>;
+
+        type BiPrivateKeyCxx;
+
+        fn generate_biprivatekey(
+            authority: &CxxString,
+            length: u32,
+        ) -> Result<Box<BiPrivateKeyCxx>>;
+        fn create_biprivatekey_path(path: &CxxString) -> Result<Box<BiPrivateKeyCxx>>;
+        fn create_biprivatekey_vec(buf: &Vec<u8>) -> Result<Box<BiPrivateKeyCxx>>;
+        fn get_authority(self: &BiPrivateKeyCxx) -> String;
+        fn get_bikey(self: &BiPrivateKeyCxx) -> BiKeyCxx;
+        fn write_to_path(self: &BiPrivateKeyCxx, path: &CxxString) -> Result<()>;
+        fn write_to_vec(self: &BiPrivateKeyCxx) -> Result<Vec<u8>>;
+        fn sign_pbo_vec(
+            buf: &Vec<u8>,
+            key: &BiPrivateKeyCxx,
+            version: BiSignVersionCxx,
+        ) -> Result<BiSignCxx>;
+
+        // VFS
+        type VfsCxx;
+
+        fn create_vfs() -> Box<VfsCxx>;
+        fn mount_pbo(self: &mut VfsCxx, path: &CxxString) -> Result<()>;
+        fn mount_dir(self: &mut VfsCxx, path: &CxxString, prefix: &CxxString) -> Result<()>;
+        fn exists(self: &VfsCxx, path: &CxxString) -> bool;
+        fn read(self: &VfsCxx, path: &CxxString) -> Result<Vec<u8>>;
+        fn list_dir(self: &VfsCxx, path: &CxxString) -> Vec<VfsDirEntryCxx>;
+        fn which_pbo(self: &VfsCxx, path: &CxxString) -> Result<String>;
+
+        // Mods
+        fn scan_mods(root: &CxxString) -> Result<ModScanCxx>;
 
         fn get_mipmap_from_paa(buf: &CxxVector<u8>, index: u32) -> Result<MipmapCxx>;
         fn get_mipmap_from_paa_vec(buf: &Vec<u8>, index: u32) -> Result<MipmapCxx>;
@@ -435,16 +1529,235 @@ mod bridge {
         pub mime_type: String,
         pub size: u32,
         pub timestamp: u32,
+        pub packing_method: u32,
+        pub packed_size: u32,
 
         pub data: Vec<u8>,
     }
 
+    #[derive(Debug)]
+    pub struct EntryInfoCxx {
+        pub filename: String,
+        pub mime_type: String,
+        pub original_size: u32,
+        pub data_size: u32,
+        pub offset: u64,
+        pub timestamp: u32,
+    }
+
     #[derive(Debug)]
     pub struct PboPropertyCxx {
         key: String,
         value: String,
     }
 
+    #[derive(Debug)]
+    pub struct ExtractResultCxx {
+        pub entry_path: String,
+        pub out_path: String,
+        pub success: bool,
+        pub error: String,
+    }
+
+    /// Values are stable, they are part of the `PBO#<code>` error message prefix.
+    #[derive(Debug)]
+    pub enum PboErrorKindCxx {
+        Io = 0,
+        NotFound = 1,
+        InvalidHeader = 2,
+        Truncated = 3,
+        Unsupported = 4,
+        InvalidArgument = 5,
+        Corrupt = 6,
+        Other = 7,
+    }
+
+    #[derive(Debug)]
+    pub struct PboErrorCxx {
+        pub kind: PboErrorKindCxx,
+        pub entry_path: String,
+        pub message: String,
+    }
+
+    #[derive(Debug)]
+    pub enum PboDiagnosticKindCxx {
+        TruncatedHeader,
+        DuplicatePath,
+        OutOfRange,
+        InvalidCharacters,
+        SuspiciousSize,
+        UnknownPackingMethod,
+        MissingHash,
+        ReaderFailed,
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct PboDiagnosticCxx {
+        pub kind: PboDiagnosticKindCxx,
+        pub entry_path: String,
+        pub message: String,
+    }
+
+    #[derive(Debug)]
+    pub enum PboDiffKindCxx {
+        Added,
+        Removed,
+        Modified,
+    }
+
+    /// `old_hash` and `new_hash` are SHA1 of the entry data, empty for added and removed entries.
+    #[derive(Debug)]
+    pub struct PboEntryDiffCxx {
+        pub filename: String,
+        pub kind: PboDiffKindCxx,
+        pub old_size: u32,
+        pub new_size: u32,
+        pub old_timestamp: u32,
+        pub new_timestamp: u32,
+        pub old_hash: Vec<u8>,
+        pub new_hash: Vec<u8>,
+        pub size_changed: bool,
+        pub timestamp_changed: bool,
+        pub content_changed: bool,
+    }
+
+    #[derive(Debug)]
+    pub struct PboPropertyDiffCxx {
+        pub key: String,
+        pub kind: PboDiffKindCxx,
+        pub old_value: String,
+        pub new_value: String,
+    }
+
+    #[derive(Debug)]
+    pub struct PboDiffCxx {
+        pub entries: Vec<PboEntryDiffCxx>,
+        pub properties: Vec<PboPropertyDiffCxx>,
+    }
+
+    #[derive(Debug)]
+    pub enum CfgValueTypeCxx {
+        String,
+        Float,
+        Long,
+        Int64,
+        Array,
+        Class,
+        Expression,
+        /// `class X;` and `delete X;` entries of a listing.
+        None,
+    }
+
+    #[derive(Debug)]
+    pub enum CfgEntryKindCxx {
+        Property,
+        Class,
+        ExternClass,
+        DeleteClass,
+    }
+
+    /// Entry of a class listing, `path` is relative to the listed class.
+    #[derive(Debug)]
+    pub struct CfgEntryInfoCxx {
+        pub name: String,
+        pub kind: CfgEntryKindCxx,
+        pub parent: String,
+        pub path: Vec<String>,
+        pub depth: u32,
+        pub value: Box<CfgValueCxx>,
+    }
+
+    #[derive(Debug)]
+    pub struct VfsDirEntryCxx {
+        pub name: String,
+        pub is_dir: bool,
+    }
+
+    #[derive(Debug)]
+    pub struct ModCxx {
+        pub name: String,
+        pub path: String,
+        pub pbos: Vec<String>,
+        pub keys: Vec<String>,
+    }
+
+    #[derive(Debug)]
+    pub struct AddonCxx {
+        pub name: String,
+        pub mod_name: String,
+        pub pbo_path: String,
+        pub required_addons: Vec<String>,
+    }
+
+    #[derive(Debug)]
+    pub struct AddonCycleCxx {
+        pub addons: Vec<String>,
+    }
+
+    #[derive(Debug)]
+    pub struct MissingAddonCxx {
+        pub addon: String,
+        pub required_by: String,
+    }
+
+    #[derive(Debug)]
+    pub struct ModScanErrorCxx {
+        pub path: String,
+        pub error: String,
+    }
+
+    #[derive(Debug)]
+    pub struct ModScanCxx {
+        pub mods: Vec<ModCxx>,
+        pub addons: Vec<AddonCxx>,
+        pub load_order: Vec<String>,
+        pub cycles: Vec<AddonCycleCxx>,
+        pub missing: Vec<MissingAddonCxx>,
+        pub errors: Vec<ModScanErrorCxx>,
+    }
+
+    #[derive(Debug)]
+    pub enum SeekOriginCxx {
+        Start,
+        Current,
+        End,
+    }
+
+    #[derive(Debug)]
+    pub struct PboHashCheckCxx {
+        pub is_valid: bool,
+        pub expected: Vec<u8>,
+        pub computed: Vec<u8>,
+    }
+
+    /// Modulus is little endian, as stored in the file.
+    #[derive(Debug)]
+    pub struct BiKeyCxx {
+        pub authority: String,
+        pub length: u32,
+        pub exponent: u32,
+        pub modulus: Vec<u8>,
+    }
+
+    /// Modulus and signatures are little endian, as stored in the file.
+    #[derive(Debug)]
+    pub struct BiSignCxx {
+        pub authority: String,
+        pub version: BiSignVersionCxx,
+        pub length: u32,
+        pub exponent: u32,
+        pub modulus: Vec<u8>,
+        pub sig1: Vec<u8>,
+        pub sig2: Vec<u8>,
+        pub sig3: Vec<u8>,
+    }
+
+    #[derive(Debug)]
+    pub enum BiSignVersionCxx {
+        V2 = 2,
+        V3 = 3,
+    }
+
     #[derive(Debug)]
     pub struct OprwCxx {
         pub version: u32,
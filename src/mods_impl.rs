@@ -0,0 +1,254 @@
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use cxx::CxxString;
+use rvff::rap::{Cfg, CfgValue};
+
+use crate::{
+    bridge::{AddonCxx, AddonCycleCxx, MissingAddonCxx, ModCxx, ModScanCxx, ModScanErrorCxx},
+    pbo_impl::{self, PboSource},
+};
+
+fn sorted_dir(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut paths = std::fs::read_dir(dir)?
+        .map(|e| Ok(e?.path()))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    paths.sort();
+    Ok(paths)
+}
+
+fn find_subdir(dir: &Path, name: &str) -> Option<PathBuf> {
+    sorted_dir(dir).ok()?.into_iter().find(|p| {
+        p.is_dir()
+            && p.file_name()
+                .is_some_and(|n| n.to_string_lossy().eq_ignore_ascii_case(name))
+    })
+}
+
+fn files_with_extension(dir: &Path, extension: &str) -> anyhow::Result<Vec<String>> {
+    Ok(sorted_dir(dir)?
+        .into_iter()
+        .filter(|p| {
+            p.is_file()
+                && p.extension()
+                    .is_some_and(|e| e.to_string_lossy().eq_ignore_ascii_case(extension))
+        })
+        .map(|p| p.to_string_lossy().into_owned())
+        .collect())
+}
+
+/// Reads the `CfgPatches` classes of every `config.bin` in a PBO.
+fn read_addons(pbo_path: &str, mod_name: &str) -> anyhow::Result<Vec<AddonCxx>> {
+    let source = PboSource::Path(PathBuf::from(pbo_path));
+    let mut reader = source.open()?;
+    let header = pbo_impl::read_header(&mut reader)?;
+
+    let mut addons = Vec::new();
+    for entry in &header.entries {
+        let name = entry.filename.rsplit('\\').next().unwrap_or_default();
+        if !name.eq_ignore_ascii_case("config.bin") {
+            continue;
+        }
+
+        let data = pbo_impl::read_entry_data(&mut reader, entry)?;
+        let cfg = Cfg::read(&mut Cursor::new(data))?;
+        let Some(patches) = cfg.get_entry(&["CfgPatches"]).and_then(|e| e.as_class()) else {
+            continue;
+        };
+
+        for patch in patches.entries.iter().filter_map(|e| e.as_class()) {
+            let required_addons = patch
+                .get_entry(&["requiredAddons"])
+                .and_then(|e| e.as_array())
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|v| match v {
+                    CfgValue::String(s) => Some(s),
+                    _ => None,
+                })
+                .collect();
+
+            addons.push(AddonCxx {
+                name: patch.name,
+                mod_name: mod_name.to_string(),
+                pbo_path: pbo_path.to_string(),
+                required_addons,
+            });
+        }
+    }
+    Ok(addons)
+}
+
+/// Orders addons so that every addon comes after its `requiredAddons`.
+///
+/// Addons that are part of or depend on a cycle are appended in discovery order.
+fn build_load_order(scan: &mut ModScanCxx) {
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for (i, addon) in scan.addons.iter().enumerate() {
+        index.entry(addon.name.to_ascii_lowercase()).or_insert(i);
+    }
+
+    let mut dependencies: Vec<Vec<usize>> = vec![Vec::new(); scan.addons.len()];
+    for (i, addon) in scan.addons.iter().enumerate() {
+        for required in &addon.required_addons {
+            match index.get(&required.to_ascii_lowercase()) {
+                Some(&dep) => dependencies[i].push(dep),
+                None => scan.missing.push(MissingAddonCxx {
+                    addon: required.clone(),
+                    required_by: addon.name.clone(),
+                }),
+            }
+        }
+    }
+
+    let mut placed = vec![false; scan.addons.len()];
+    let mut progress = true;
+    while progress {
+        progress = false;
+        for i in 0..scan.addons.len() {
+            if !placed[i] && dependencies[i].iter().all(|&d| placed[d]) {
+                placed[i] = true;
+                progress = true;
+                scan.load_order.push(scan.addons[i].name.clone());
+            }
+        }
+    }
+
+    let remaining: Vec<usize> = (0..scan.addons.len()).filter(|&i| !placed[i]).collect();
+    for component in strongly_connected(&remaining, &dependencies) {
+        if component.len() > 1 || dependencies[component[0]].contains(&component[0]) {
+            scan.cycles.push(AddonCycleCxx {
+                addons: component
+                    .iter()
+                    .map(|&i| scan.addons[i].name.clone())
+                    .collect(),
+            });
+        }
+    }
+    for i in remaining {
+        scan.load_order.push(scan.addons[i].name.clone());
+    }
+}
+
+/// Tarjan's algorithm restricted to `nodes`, components are sorted by discovery order.
+fn strongly_connected(nodes: &[usize], edges: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    struct State<'a> {
+        edges: &'a [Vec<usize>],
+        member: Vec<bool>,
+        index: Vec<Option<usize>>,
+        low: Vec<usize>,
+        on_stack: Vec<bool>,
+        stack: Vec<usize>,
+        next: usize,
+        components: Vec<Vec<usize>>,
+    }
+
+    fn visit(s: &mut State, v: usize) {
+        s.index[v] = Some(s.next);
+        s.low[v] = s.next;
+        s.next += 1;
+        s.stack.push(v);
+        s.on_stack[v] = true;
+
+        for &w in s.edges[v].iter() {
+            if !s.member[w] {
+                continue;
+            }
+            match s.index[w] {
+                None => {
+                    visit(s, w);
+                    s.low[v] = s.low[v].min(s.low[w]);
+                }
+                Some(index) if s.on_stack[w] => s.low[v] = s.low[v].min(index),
+                _ => {}
+            }
+        }
+
+        if Some(s.low[v]) == s.index[v] {
+            let mut component = Vec::new();
+            while let Some(w) = s.stack.pop() {
+                s.on_stack[w] = false;
+                component.push(w);
+                if w == v {
+                    break;
+                }
+            }
+            component.sort_unstable();
+            s.components.push(component);
+        }
+    }
+
+    let mut state = State {
+        edges,
+        member: vec![false; edges.len()],
+        index: vec![None; edges.len()],
+        low: vec![0; edges.len()],
+        on_stack: vec![false; edges.len()],
+        stack: Vec::new(),
+        next: 0,
+        components: Vec::new(),
+    };
+    for &v in nodes {
+        state.member[v] = true;
+    }
+    for &v in nodes {
+        if state.index[v].is_none() {
+            visit(&mut state, v);
+        }
+    }
+
+    state.components.sort_by_key(|c| c[0]);
+    state.components
+}
+
+/// Scans `root` for mod folders (`@Mod/addons/*.pbo`, `@Mod/keys/*.bikey`) and orders their addons.
+pub fn scan_mods(root: &CxxString) -> anyhow::Result<ModScanCxx> {
+    let root = PathBuf::from(root.to_string());
+    let mut scan = ModScanCxx {
+        mods: Vec::new(),
+        addons: Vec::new(),
+        load_order: Vec::new(),
+        cycles: Vec::new(),
+        missing: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    for dir in sorted_dir(&root)?.into_iter().filter(|p| p.is_dir()) {
+        let Some(addons_dir) = find_subdir(&dir, "addons") else {
+            continue;
+        };
+
+        let name = dir
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let pbos = files_with_extension(&addons_dir, "pbo")?;
+        let keys = match find_subdir(&dir, "keys") {
+            Some(keys_dir) => files_with_extension(&keys_dir, "bikey")?,
+            None => Vec::new(),
+        };
+
+        for pbo in &pbos {
+            match read_addons(pbo, &name) {
+                Ok(addons) => scan.addons.extend(addons),
+                Err(e) => scan.errors.push(ModScanErrorCxx {
+                    path: pbo.clone(),
+                    error: e.to_string(),
+                }),
+            }
+        }
+
+        scan.mods.push(ModCxx {
+            name,
+            path: dir.to_string_lossy().into_owned(),
+            pbos,
+            keys,
+        });
+    }
+
+    build_load_order(&mut scan);
+    Ok(scan)
+}
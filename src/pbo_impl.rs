@@ -1,29 +1,1536 @@
-use rvff::pbo::{Entry, Pbo};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::File,
+    io::{self, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
 
-use crate::bridge::{EntryCxx, PboCxx, PboPropertyCxx};
+use globset::{GlobBuilder, GlobMatcher};
+use memmap2::Mmap;
 
-impl From<Entry> for EntryCxx {
-    fn from(entry: Entry) -> Self {
+use sha1::{Digest, Sha1};
+
+use crate::bridge::{
+    EntryCxx, EntryInfoCxx, ExtractResultCxx, PboDiagnosticCxx, PboDiagnosticKindCxx, PboDiffCxx,
+    PboDiffKindCxx, PboEntryDiffCxx, PboErrorCxx, PboErrorKindCxx, PboHashCheckCxx,
+    PboPropertyDiffCxx, SeekOriginCxx,
+};
+
+pub(crate) const PACKING_METHOD_UNCOMPRESSED: u32 = 0x0000_0000;
+pub(crate) const PACKING_METHOD_COMPRESSED: u32 = 0x4370_7273;
+pub(crate) const PACKING_METHOD_VERSION: u32 = 0x5665_7273;
+
+impl From<&HeaderEntry> for EntryInfoCxx {
+    fn from(entry: &HeaderEntry) -> Self {
         Self {
-            filename: entry.filename,
-            mime_type: entry.mime_type,
-            size: entry.original_size,
+            filename: entry.filename.clone(),
+            mime_type: mime_from_packing_method(entry.packing_method),
+            original_size: entry.original_size,
+            data_size: entry.data_size,
+            offset: entry.offset,
             timestamp: entry.timestamp,
-            data: entry.data,
         }
     }
 }
 
-impl From<Pbo> for PboCxx {
-    fn from(pbo: Pbo) -> Self {
+impl PboErrorKindCxx {
+    const ALL: [PboErrorKindCxx; 8] = [
+        PboErrorKindCxx::Io,
+        PboErrorKindCxx::NotFound,
+        PboErrorKindCxx::InvalidHeader,
+        PboErrorKindCxx::Truncated,
+        PboErrorKindCxx::Unsupported,
+        PboErrorKindCxx::InvalidArgument,
+        PboErrorKindCxx::Corrupt,
+        PboErrorKindCxx::Other,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            PboErrorKindCxx::Io => "Io",
+            PboErrorKindCxx::NotFound => "NotFound",
+            PboErrorKindCxx::InvalidHeader => "InvalidHeader",
+            PboErrorKindCxx::Truncated => "Truncated",
+            PboErrorKindCxx::Unsupported => "Unsupported",
+            PboErrorKindCxx::InvalidArgument => "InvalidArgument",
+            PboErrorKindCxx::Corrupt => "Corrupt",
+            _ => "Other",
+        }
+    }
+
+    fn from_code(code: u8) -> Self {
+        Self::ALL
+            .into_iter()
+            .find(|k| k.repr == code)
+            .unwrap_or(PboErrorKindCxx::Other)
+    }
+}
+
+/// Prefix of every `PboError` message, followed by the numeric `PboErrorKindCxx` code.
+const PBO_ERROR_PREFIX: &str = "PBO#";
+
+/// Error of a PBO operation.
+///
+/// Displayed as `PBO#<code> [<Kind>] <<entry>>: <message>`, where `<code>` is the stable
+/// `PboErrorKindCxx` value and the `<<entry>>: ` part is left out without an entry. The C++
+/// side can check for the `PBO#` prefix of `rust::Error::what()` and read the code, or use
+/// `parse_pbo_error`. Entry paths can't contain `<` or `>`.
+#[derive(Debug)]
+pub(crate) struct PboError {
+    pub kind: PboErrorKindCxx,
+    pub entry_path: String,
+    pub message: String,
+}
+
+impl PboError {
+    pub fn new(kind: PboErrorKindCxx, entry_path: &str, message: impl fmt::Display) -> Self {
         Self {
-            properties: pbo
-                .properties
-                .into_iter()
-                .map(|(key, value)| PboPropertyCxx { key, value })
-                .collect(),
-            entries: pbo.entries.into_iter().map(|(_, e)| e.into()).collect(),
-            hash: pbo.hash,
+            kind,
+            entry_path: entry_path.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    /// Wraps an error of an underlying reader, keeping it if it already is a `PboError`.
+    ///
+    /// `default_kind` is used for unexpected EOFs and errors that aren't I/O errors.
+    pub fn wrap(default_kind: PboErrorKindCxx, entry_path: &str, err: anyhow::Error) -> Self {
+        let err = match err.downcast::<PboError>() {
+            Ok(mut err) => {
+                if err.entry_path.is_empty() {
+                    err.entry_path = entry_path.to_string();
+                }
+                return err;
+            }
+            Err(err) => err,
+        };
+
+        let kind = match err.downcast_ref::<io::Error>().map(|e| e.kind()) {
+            Some(io::ErrorKind::UnexpectedEof) => default_kind,
+            Some(io::ErrorKind::NotFound) => PboErrorKindCxx::NotFound,
+            Some(_) => PboErrorKindCxx::Io,
+            None => default_kind,
+        };
+        Self::new(kind, entry_path, err)
+    }
+
+    /// I/O error on a file outside the PBO, like an extraction target or a writer input.
+    pub fn file(entry_path: &str, path: &Path, err: io::Error) -> Self {
+        let kind = match err.kind() {
+            io::ErrorKind::NotFound => PboErrorKindCxx::NotFound,
+            _ => PboErrorKindCxx::Io,
+        };
+        Self::new(kind, entry_path, format!("{}: {}", path.display(), err))
+    }
+
+    pub fn parse(what: &str) -> PboErrorCxx {
+        let parsed = what.strip_prefix(PBO_ERROR_PREFIX).and_then(|rest| {
+            let (code, rest) = rest.split_once(' ')?;
+            let code = code.parse::<u8>().ok()?;
+            let rest = match rest.strip_prefix('[') {
+                Some(rest) => rest.split_once("] ")?.1,
+                None => rest,
+            };
+            let (entry_path, message) = match rest.strip_prefix('<') {
+                Some(rest) => rest.split_once(">: ")?,
+                None => ("", rest),
+            };
+            Some(PboErrorCxx {
+                kind: PboErrorKindCxx::from_code(code),
+                entry_path: entry_path.to_string(),
+                message: message.to_string(),
+            })
+        });
+
+        parsed.unwrap_or_else(|| PboErrorCxx {
+            kind: PboErrorKindCxx::Other,
+            entry_path: String::new(),
+            message: what.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for PboError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{} [{}] ",
+            PBO_ERROR_PREFIX,
+            self.kind.repr,
+            self.kind.name()
+        )?;
+        if !self.entry_path.is_empty() {
+            write!(f, "<{}>: ", self.entry_path)?;
+        }
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for PboError {}
+
+pub(crate) trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// Reference counted PBO bytes (owned or memory mapped), so cursors over them don't copy.
+#[derive(Clone)]
+pub(crate) struct SharedBuffer(Arc<dyn AsRef<[u8]> + Send + Sync>);
+
+impl SharedBuffer {
+    pub fn new(buf: Vec<u8>) -> Self {
+        Self(Arc::new(buf))
+    }
+
+    /// The file must not be modified while mapped.
+    pub fn map(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: callers of the mmap backend guarantee the file isn't modified while mapped.
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(Self(Arc::new(mmap)))
+    }
+}
+
+impl AsRef<[u8]> for SharedBuffer {
+    fn as_ref(&self) -> &[u8] {
+        (*self.0).as_ref()
+    }
+}
+
+/// Where a `PboReaderCxx` got its data from, so the raw bytes can be read again.
+#[derive(Clone)]
+pub(crate) enum PboSource {
+    /// Reopened on every read, for sources that are only read occasionally (VFS mounts, mod scans).
+    Path(PathBuf),
+    /// Opened once, all reads go to the same file even if the path is replaced.
+    File(Arc<File>),
+    Memory(SharedBuffer),
+}
+
+impl PboSource {
+    pub fn open_file(path: &Path) -> io::Result<Self> {
+        Ok(PboSource::File(Arc::new(File::open(path)?)))
+    }
+
+    pub fn open(&self) -> io::Result<Box<dyn ReadSeek>> {
+        Ok(match self {
+            PboSource::Path(path) => Box::new(BufReader::new(File::open(path)?)),
+            PboSource::File(file) => Box::new(BufReader::new(FileCursor {
+                file: file.clone(),
+                pos: 0,
+            })),
+            PboSource::Memory(buf) => Box::new(Cursor::new(buf.clone())),
+        })
+    }
+
+    /// Whether both sources read the same file or buffer.
+    fn same(&self, other: &PboSource) -> bool {
+        match (self, other) {
+            (PboSource::Path(a), PboSource::Path(b)) => a == b,
+            (PboSource::File(a), PboSource::File(b)) => Arc::ptr_eq(a, b),
+            (PboSource::Memory(a), PboSource::Memory(b)) => Arc::ptr_eq(&a.0, &b.0),
+            _ => false,
+        }
+    }
+
+    /// Returns the stored bytes of an uncompressed entry without copying them.
+    pub fn entry_slice(&self, entry: &HeaderEntry) -> anyhow::Result<&[u8]> {
+        let PboSource::Memory(buf) = self else {
+            return Err(PboError::new(
+                PboErrorKindCxx::Unsupported,
+                &entry.filename,
+                "Borrowed entry data needs a memory or mmap backed reader",
+            )
+            .into());
+        };
+        if entry.packing_method == PACKING_METHOD_COMPRESSED {
+            return Err(PboError::new(
+                PboErrorKindCxx::Unsupported,
+                &entry.filename,
+                "Compressed entries can't be borrowed",
+            )
+            .into());
+        }
+
+        let start = entry.offset as usize;
+        buf.as_ref()
+            .get(start..start + entry.data_size as usize)
+            .ok_or_else(|| {
+                PboError::new(
+                    PboErrorKindCxx::Truncated,
+                    &entry.filename,
+                    "Entry data truncated",
+                )
+                .into()
+            })
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct HeaderEntry {
+    pub filename: String,
+    pub packing_method: u32,
+    pub original_size: u32,
+    pub timestamp: u32,
+    pub data_size: u32,
+    pub offset: u64,
+}
+
+pub(crate) struct PboHeader {
+    pub properties: Vec<(String, String)>,
+    pub entries: Vec<HeaderEntry>,
+    pub data_end: u64,
+}
+
+impl PboHeader {
+    pub fn find_entry(&self, entry_path: &str) -> Option<&HeaderEntry> {
+        let entry_path = normalize_entry_path(entry_path);
+        self.entries
+            .iter()
+            .find(|e| e.filename.eq_ignore_ascii_case(&entry_path))
+    }
+
+    pub fn get_property(&self, key: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+pub(crate) fn read_cstring<R: Read>(reader: &mut R) -> io::Result<String> {
+    let mut buf = Vec::new();
+    let mut byte = [0_u8; 1];
+    loop {
+        reader.read_exact(&mut byte)?;
+        if byte[0] == 0 {
+            break;
+        }
+        buf.push(byte[0]);
+    }
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+pub(crate) fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0_u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+type ParsedHeader = (
+    Vec<(String, String)>,
+    Vec<HeaderEntry>,
+    Result<u64, (u64, io::Error)>,
+);
+
+/// Parses header records until the terminator, also returning what was read before an error.
+///
+/// Returns properties, entries and the data offset, or the error and the offset after the
+/// last complete record.
+fn parse_header<R: Read + Seek>(reader: &mut R) -> ParsedHeader {
+    let mut properties = Vec::new();
+    let mut entries = Vec::new();
+    let mut first = true;
+    let mut parsed_end = 0;
+    let mut parse = || -> io::Result<u64> {
+        reader.rewind()?;
+        loop {
+            let filename = read_cstring(reader)?;
+            let packing_method = read_u32(reader)?;
+            let original_size = read_u32(reader)?;
+            let _reserved = read_u32(reader)?;
+            let timestamp = read_u32(reader)?;
+            let data_size = read_u32(reader)?;
+
+            if filename.is_empty() {
+                if packing_method == PACKING_METHOD_VERSION && first {
+                    loop {
+                        let key = read_cstring(reader)?;
+                        if key.is_empty() {
+                            break;
+                        }
+                        let value = read_cstring(reader)?;
+                        properties.push((key, value));
+                    }
+                    first = false;
+                    parsed_end = reader.stream_position()?;
+                    continue;
+                }
+                return reader.stream_position();
+            }
+
+            first = false;
+            entries.push(HeaderEntry {
+                filename,
+                packing_method,
+                original_size,
+                timestamp,
+                data_size,
+                offset: 0,
+            });
+            parsed_end = reader.stream_position()?;
+        }
+    };
+    let data_start = parse().map_err(|e| (parsed_end, e));
+
+    (properties, entries, data_start)
+}
+
+fn assign_offsets(entries: &mut [HeaderEntry], data_start: u64) -> u64 {
+    let mut offset = data_start;
+    for entry in entries {
+        entry.offset = offset;
+        offset += u64::from(entry.data_size);
+    }
+    offset
+}
+
+/// Reads the PBO header and computes the absolute data offset of every entry.
+pub(crate) fn read_header<R: Read + Seek>(reader: &mut R) -> anyhow::Result<PboHeader> {
+    let (properties, mut entries, data_start) = parse_header(reader);
+    let data_end = assign_offsets(&mut entries, data_start.map_err(|(_, e)| e)?);
+
+    Ok(PboHeader {
+        properties,
+        entries,
+        data_end,
+    })
+}
+
+fn diagnostic(
+    kind: PboDiagnosticKindCxx,
+    entry_path: &str,
+    message: impl fmt::Display,
+) -> PboDiagnosticCxx {
+    PboDiagnosticCxx {
+        kind,
+        entry_path: entry_path.to_string(),
+        message: message.to_string(),
+    }
+}
+
+/// Reads as much of a damaged header as possible, dropping repeated paths and entries whose data
+/// isn't in the file.
+pub(crate) fn read_header_lenient<R: Read + Seek>(
+    reader: &mut R,
+    diagnostics: &mut Vec<PboDiagnosticCxx>,
+) -> anyhow::Result<PboHeader> {
+    let file_size = reader.seek(SeekFrom::End(0))?;
+    let (properties, mut entries, data_start) = parse_header(reader);
+    let data_start = data_start.unwrap_or_else(|(parsed_end, e)| {
+        diagnostics.push(diagnostic(
+            PboDiagnosticKindCxx::TruncatedHeader,
+            "",
+            format!(
+                "Header ends after {} entries at offset {}: {}",
+                entries.len(),
+                parsed_end,
+                e
+            ),
+        ));
+        parsed_end
+    });
+    let data_end = assign_offsets(&mut entries, data_start);
+
+    let mut seen = HashSet::new();
+    entries.retain(|entry| {
+        let name = &entry.filename;
+        if name
+            .chars()
+            .any(|c| c.is_control() || matches!(c, '<' | '>' | ':' | '"' | '|' | '?' | '*'))
+        {
+            diagnostics.push(diagnostic(
+                PboDiagnosticKindCxx::InvalidCharacters,
+                name,
+                "Entry path contains invalid characters",
+            ));
+        }
+        if name.split(['\\', '/']).any(|part| part == "..") || name.starts_with(['\\', '/']) {
+            diagnostics.push(diagnostic(
+                PboDiagnosticKindCxx::InvalidCharacters,
+                name,
+                "Entry path escapes the PBO root",
+            ));
+        }
+
+        if !seen.insert(normalize_entry_path(name).to_ascii_lowercase()) {
+            diagnostics.push(diagnostic(
+                PboDiagnosticKindCxx::DuplicatePath,
+                name,
+                "Duplicate entry path, only the first one is kept",
+            ));
+            return false;
+        }
+
+        match entry.packing_method {
+            PACKING_METHOD_UNCOMPRESSED
+                if entry.data_size != entry.original_size && entry.original_size != 0 =>
+            {
+                diagnostics.push(diagnostic(
+                    PboDiagnosticKindCxx::SuspiciousSize,
+                    name,
+                    format!(
+                        "Stored size {} differs from original size {}",
+                        entry.data_size, entry.original_size
+                    ),
+                ));
+            }
+            PACKING_METHOD_UNCOMPRESSED | PACKING_METHOD_COMPRESSED => {}
+            method => diagnostics.push(diagnostic(
+                PboDiagnosticKindCxx::UnknownPackingMethod,
+                name,
+                format!("Unknown packing method 0x{:08x}", method),
+            )),
+        }
+
+        let end = entry.offset + u64::from(entry.data_size);
+        if end > file_size {
+            diagnostics.push(diagnostic(
+                PboDiagnosticKindCxx::OutOfRange,
+                name,
+                format!(
+                    "Data at {}..{} is outside of the file ({} bytes), entry dropped",
+                    entry.offset, end, file_size
+                ),
+            ));
+            return false;
+        }
+        true
+    });
+
+    let header = PboHeader {
+        properties,
+        entries,
+        data_end,
+    };
+    if data_end > file_size || read_stored_hash(reader, &header)?.is_empty() {
+        diagnostics.push(diagnostic(
+            PboDiagnosticKindCxx::MissingHash,
+            "",
+            "No SHA1 trailer after the data",
+        ));
+    }
+    Ok(header)
+}
+
+/// Returns the SHA1 stored behind the data, or an empty `Vec` if there is none.
+pub(crate) fn read_stored_hash<R: Read + Seek>(
+    reader: &mut R,
+    header: &PboHeader,
+) -> anyhow::Result<Vec<u8>> {
+    let mut trailer = Vec::new();
+    reader.seek(SeekFrom::Start(header.data_end))?;
+    reader.take(21).read_to_end(&mut trailer)?;
+
+    Ok(match trailer.split_first() {
+        Some((0, hash)) if hash.len() == 20 => hash.to_vec(),
+        _ => Vec::new(),
+    })
+}
+
+/// Recomputes the SHA1 over header and data and compares it with the stored trailer.
+pub(crate) fn check_hash<R: Read + Seek>(reader: &mut R) -> anyhow::Result<PboHashCheckCxx> {
+    let header =
+        read_header(reader).map_err(|e| PboError::wrap(PboErrorKindCxx::InvalidHeader, "", e))?;
+    check_header_hash(reader, &header)
+}
+
+/// Same as `check_hash`, for an already parsed header.
+pub(crate) fn check_header_hash<R: Read + Seek>(
+    reader: &mut R,
+    header: &PboHeader,
+) -> anyhow::Result<PboHashCheckCxx> {
+    reader.rewind()?;
+    let mut hasher = Sha1::new();
+    let hashed = io::copy(&mut reader.take(header.data_end), &mut hasher)?;
+    if hashed != header.data_end {
+        return Err(PboError::new(
+            PboErrorKindCxx::Truncated,
+            "",
+            format!(
+                "Data truncated, expected {} bytes but found {}",
+                header.data_end, hashed
+            ),
+        )
+        .into());
+    }
+    let computed = hasher.finalize().to_vec();
+    let expected = read_stored_hash(reader, header)?;
+
+    Ok(PboHashCheckCxx {
+        is_valid: expected == computed,
+        expected,
+        computed,
+    })
+}
+
+/// Reads the data of an entry, decompressing `Cprs` entries.
+pub(crate) fn read_entry_data<R: Read + Seek>(
+    reader: &mut R,
+    entry: &HeaderEntry,
+) -> anyhow::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(entry.data_size as usize);
+    reader.seek(SeekFrom::Start(entry.offset))?;
+    reader
+        .take(u64::from(entry.data_size))
+        .read_to_end(&mut data)?;
+    if data.len() != entry.data_size as usize {
+        return Err(PboError::new(
+            PboErrorKindCxx::Truncated,
+            &entry.filename,
+            "Entry data truncated",
+        )
+        .into());
+    }
+
+    unpack_entry_data(entry, data)
+}
+
+/// Decompresses the stored bytes of a `Cprs` entry, other entries are returned as is.
+fn unpack_entry_data(entry: &HeaderEntry, data: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    if entry.packing_method != PACKING_METHOD_COMPRESSED {
+        return Ok(data);
+    }
+
+    let data = rvff::core::decompress_lzss_unk_size(&mut Cursor::new(data))
+        .map_err(|e| PboError::wrap(PboErrorKindCxx::Corrupt, &entry.filename, e.into()))?;
+    if data.len() != entry.original_size as usize {
+        return Err(PboError::new(
+            PboErrorKindCxx::Corrupt,
+            &entry.filename,
+            format!(
+                "Decompressed to {} bytes, expected {}",
+                data.len(),
+                entry.original_size
+            ),
+        )
+        .into());
+    }
+    Ok(data)
+}
+
+pub(crate) fn read_entry(source: &PboSource, entry: &HeaderEntry) -> anyhow::Result<EntryCxx> {
+    let mut reader = source
+        .open()
+        .map_err(|e| PboError::wrap(PboErrorKindCxx::Io, &entry.filename, e.into()))?;
+
+    Ok(entry_with_data(entry, read_entry_data(&mut reader, entry)?))
+}
+
+pub(crate) fn entry_with_data(entry: &HeaderEntry, data: Vec<u8>) -> EntryCxx {
+    EntryCxx {
+        filename: entry.filename.clone(),
+        mime_type: mime_from_packing_method(entry.packing_method),
+        size: entry.original_size,
+        timestamp: entry.timestamp,
+        packing_method: entry.packing_method,
+        packed_size: entry.data_size,
+        data,
+    }
+}
+
+/// Reads at `offset` without using the file's cursor.
+fn read_file_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    #[cfg(unix)]
+    return std::os::unix::fs::FileExt::read_at(file, buf, offset);
+    #[cfg(windows)]
+    return std::os::windows::fs::FileExt::seek_read(file, buf, offset);
+}
+
+/// Cursor over a shared file handle, reading by offset so cursors don't interfere.
+struct FileCursor {
+    file: Arc<File>,
+    pos: u64,
+}
+
+impl Read for FileCursor {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = read_file_at(&self.file, buf, self.pos)?;
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for FileCursor {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.file.metadata()?.len().checked_add_signed(delta),
+        };
+        self.pos = pos.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Seek before start of file")
+        })?;
+        Ok(self.pos)
+    }
+}
+
+/// PBO bytes read by offset instead of through a cursor, so it can be shared between threads.
+pub(crate) enum PositionalSource {
+    File(Arc<File>),
+    Memory(SharedBuffer),
+}
+
+impl PositionalSource {
+    pub fn new(source: &PboSource) -> io::Result<Self> {
+        Ok(match source {
+            PboSource::Path(path) => PositionalSource::File(Arc::new(File::open(path)?)),
+            PboSource::File(file) => PositionalSource::File(file.clone()),
+            PboSource::Memory(buf) => PositionalSource::Memory(buf.clone()),
+        })
+    }
+
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        match self {
+            PositionalSource::File(file) => {
+                let mut read = 0;
+                while read < buf.len() {
+                    match read_file_at(file, &mut buf[read..], offset + read as u64) {
+                        Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                        Ok(n) => read += n,
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+                Ok(())
+            }
+            PositionalSource::Memory(data) => {
+                let data = data.as_ref();
+                let start = usize::try_from(offset).unwrap_or(usize::MAX);
+                let src = start
+                    .checked_add(buf.len())
+                    .and_then(|end| data.get(start..end))
+                    .ok_or(io::ErrorKind::UnexpectedEof)?;
+                buf.copy_from_slice(src);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn read_entry(&self, entry: &HeaderEntry) -> anyhow::Result<EntryCxx> {
+        let mut data = vec![0_u8; entry.data_size as usize];
+        self.read_exact_at(&mut data, entry.offset).map_err(|e| {
+            let kind = match e.kind() {
+                io::ErrorKind::UnexpectedEof => PboErrorKindCxx::Truncated,
+                _ => PboErrorKindCxx::Io,
+            };
+            PboError::new(kind, &entry.filename, e)
+        })?;
+
+        Ok(entry_with_data(entry, unpack_entry_data(entry, data)?))
+    }
+}
+
+/// SHA1 of the (decompressed) entry data.
+fn hash_entry_data<R: Read + Seek>(reader: &mut R, entry: &HeaderEntry) -> anyhow::Result<Vec<u8>> {
+    if entry.packing_method == PACKING_METHOD_COMPRESSED {
+        return Ok(Sha1::digest(read_entry_data(reader, entry)?).to_vec());
+    }
+
+    let mut hasher = Sha1::new();
+    reader.seek(SeekFrom::Start(entry.offset))?;
+    let size = u64::from(entry.data_size);
+    if io::copy(&mut reader.take(size), &mut hasher)? != size {
+        return Err(PboError::new(
+            PboErrorKindCxx::Truncated,
+            &entry.filename,
+            "Entry data truncated",
+        )
+        .into());
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Maps lowercase entry paths to the first entry with that path.
+fn entry_index(header: &PboHeader) -> HashMap<String, &HeaderEntry> {
+    let mut index = HashMap::with_capacity(header.entries.len());
+    for entry in &header.entries {
+        index
+            .entry(normalize_entry_path(&entry.filename).to_ascii_lowercase())
+            .or_insert(entry);
+    }
+    index
+}
+
+/// Compares two PBOs, reporting only changed entries and properties.
+///
+/// Entries are matched by path like `find_entry`, so only the first of duplicate paths is
+/// compared. Entry data is only hashed for entries present in both PBOs.
+pub(crate) fn diff_pbos(
+    old_source: &PboSource,
+    old_header: &PboHeader,
+    new_source: &PboSource,
+    new_header: &PboHeader,
+) -> anyhow::Result<PboDiffCxx> {
+    let old_index = entry_index(old_header);
+    let new_index = entry_index(new_header);
+
+    let mut old_reader = old_source.open()?;
+    let mut new_reader = new_source.open()?;
+
+    let mut entries = Vec::new();
+    for old in &old_header.entries {
+        let key = normalize_entry_path(&old.filename).to_ascii_lowercase();
+        if !std::ptr::eq(old_index[&key], old) {
+            continue;
+        }
+        let Some(new) = new_index.get(&key) else {
+            entries.push(PboEntryDiffCxx {
+                filename: old.filename.clone(),
+                kind: PboDiffKindCxx::Removed,
+                old_size: old.original_size,
+                new_size: 0,
+                old_timestamp: old.timestamp,
+                new_timestamp: 0,
+                old_hash: Vec::new(),
+                new_hash: Vec::new(),
+                size_changed: true,
+                timestamp_changed: true,
+                content_changed: true,
+            });
+            continue;
+        };
+
+        let old_hash = hash_entry_data(&mut old_reader, old)?;
+        let new_hash = hash_entry_data(&mut new_reader, new)?;
+        let size_changed = old.original_size != new.original_size;
+        let timestamp_changed = old.timestamp != new.timestamp;
+        let content_changed = old_hash != new_hash;
+        if size_changed || timestamp_changed || content_changed {
+            entries.push(PboEntryDiffCxx {
+                filename: new.filename.clone(),
+                kind: PboDiffKindCxx::Modified,
+                old_size: old.original_size,
+                new_size: new.original_size,
+                old_timestamp: old.timestamp,
+                new_timestamp: new.timestamp,
+                old_hash,
+                new_hash,
+                size_changed,
+                timestamp_changed,
+                content_changed,
+            });
+        }
+    }
+
+    for new in &new_header.entries {
+        let key = normalize_entry_path(&new.filename).to_ascii_lowercase();
+        if std::ptr::eq(new_index[&key], new) && !old_index.contains_key(&key) {
+            entries.push(PboEntryDiffCxx {
+                filename: new.filename.clone(),
+                kind: PboDiffKindCxx::Added,
+                old_size: 0,
+                new_size: new.original_size,
+                old_timestamp: 0,
+                new_timestamp: new.timestamp,
+                old_hash: Vec::new(),
+                new_hash: Vec::new(),
+                size_changed: true,
+                timestamp_changed: true,
+                content_changed: true,
+            });
+        }
+    }
+
+    let mut properties = Vec::new();
+    for (key, old_value) in &old_header.properties {
+        match new_header.get_property(key) {
+            Some(new_value) if new_value == old_value => {}
+            Some(new_value) => properties.push(PboPropertyDiffCxx {
+                key: key.clone(),
+                kind: PboDiffKindCxx::Modified,
+                old_value: old_value.clone(),
+                new_value: new_value.to_string(),
+            }),
+            None => properties.push(PboPropertyDiffCxx {
+                key: key.clone(),
+                kind: PboDiffKindCxx::Removed,
+                old_value: old_value.clone(),
+                new_value: String::new(),
+            }),
+        }
+    }
+    for (key, new_value) in &new_header.properties {
+        if old_header.get_property(key).is_none() {
+            properties.push(PboPropertyDiffCxx {
+                key: key.clone(),
+                kind: PboDiffKindCxx::Added,
+                old_value: String::new(),
+                new_value: new_value.clone(),
+            });
+        }
+    }
+
+    Ok(PboDiffCxx {
+        entries,
+        properties,
+    })
+}
+
+/// Compresses `data` with the LZSS variant of `Cprs` entries, including the trailing checksum.
+pub(crate) fn compress_lzss(data: &[u8]) -> Vec<u8> {
+    const WINDOW: usize = 0x0FFF;
+    const MIN_MATCH: usize = 3;
+    const MAX_MATCH: usize = 0x0F + MIN_MATCH;
+    const MAX_CANDIDATES: usize = 64;
+
+    let key = |pos: usize| [data[pos], data[pos + 1], data[pos + 2]];
+    let mut candidates: HashMap<[u8; 3], Vec<usize>> = HashMap::new();
+    let mut out = Vec::with_capacity(data.len() + data.len() / 8 + 5);
+    let mut pos = 0;
+    while pos < data.len() {
+        let flag_index = out.len();
+        out.push(0_u8);
+
+        for bit in 0..8 {
+            if pos >= data.len() {
+                break;
+            }
+
+            let (mut best_len, mut best_dist) = (0, 0);
+            if pos + MIN_MATCH <= data.len() {
+                let starts = candidates.get(&key(pos)).map(Vec::as_slice);
+                for &start in starts.unwrap_or_default().iter().rev() {
+                    let dist = pos - start;
+                    if dist > WINDOW {
+                        break;
+                    }
+                    let len = data[start..]
+                        .iter()
+                        .zip(&data[pos..])
+                        .take(MAX_MATCH)
+                        .take_while(|(a, b)| a == b)
+                        .count();
+                    if len > best_len {
+                        best_len = len;
+                        best_dist = dist;
+                        if len == MAX_MATCH {
+                            break;
+                        }
+                    }
+                }
+            }
+
+            let step = if best_len >= MIN_MATCH {
+                out.push((best_dist & 0xFF) as u8);
+                out.push((((best_dist >> 4) & 0xF0) | (best_len - MIN_MATCH)) as u8);
+                best_len
+            } else {
+                out[flag_index] |= 1 << bit;
+                out.push(data[pos]);
+                1
+            };
+
+            for p in pos..(pos + step).min(data.len().saturating_sub(MIN_MATCH - 1)) {
+                let starts = candidates.entry(key(p)).or_default();
+                if starts.len() == MAX_CANDIDATES * 2 {
+                    starts.drain(..MAX_CANDIDATES);
+                }
+                starts.push(p);
+            }
+            pos += step;
+        }
+    }
+
+    let checksum = data
+        .iter()
+        .fold(0_u32, |sum, b| sum.wrapping_add(u32::from(*b)));
+    out.extend_from_slice(&checksum.to_le_bytes());
+    out
+}
+
+/// Seekable view over the data of a single entry.
+pub struct PboEntryStreamCxx {
+    reader: Box<dyn ReadSeek>,
+    offset: u64,
+    size: u64,
+    position: u64,
+}
+
+impl PboEntryStreamCxx {
+    /// Compressed entries are decompressed up front and streamed from memory.
+    pub(crate) fn new(source: &PboSource, entry: &HeaderEntry) -> anyhow::Result<Self> {
+        if entry.packing_method == PACKING_METHOD_COMPRESSED {
+            let data = read_entry_data(&mut source.open()?, entry)?;
+            return Ok(Self {
+                size: data.len() as u64,
+                reader: Box::new(Cursor::new(data)),
+                offset: 0,
+                position: 0,
+            });
+        }
+
+        Ok(Self {
+            reader: source.open()?,
+            offset: entry.offset,
+            size: u64::from(entry.data_size),
+            position: 0,
+        })
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> anyhow::Result<usize> {
+        let remaining = self.size.saturating_sub(self.position);
+        let len = buf
+            .len()
+            .min(usize::try_from(remaining).unwrap_or(usize::MAX));
+        if len == 0 {
+            return Ok(0);
+        }
+
+        self.reader
+            .seek(SeekFrom::Start(self.offset + self.position))?;
+        self.reader.read_exact(&mut buf[..len])?;
+        self.position += len as u64;
+        Ok(len)
+    }
+
+    pub fn seek(&mut self, offset: i64, origin: SeekOriginCxx) -> anyhow::Result<u64> {
+        let base = match origin {
+            SeekOriginCxx::Start => 0,
+            SeekOriginCxx::Current => self.position,
+            SeekOriginCxx::End => self.size,
+            _ => {
+                return Err(PboError::new(
+                    PboErrorKindCxx::InvalidArgument,
+                    "",
+                    "Invalid seek origin",
+                )
+                .into())
+            }
+        };
+
+        self.position = base.checked_add_signed(offset).ok_or_else(|| {
+            PboError::new(
+                PboErrorKindCxx::InvalidArgument,
+                "",
+                "Seek before start of entry",
+            )
+        })?;
+        Ok(self.position)
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn tell(&self) -> u64 {
+        self.position
+    }
+}
+
+/// Glob filter over entry paths. Patterns without a separator match the file name only.
+pub(crate) struct EntryFilter {
+    include: Vec<(GlobMatcher, bool)>,
+    exclude: Vec<(GlobMatcher, bool)>,
+}
+
+impl EntryFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> anyhow::Result<Self> {
+        let build = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|p| {
+                    let p = p.replace('\\', "/");
+                    let glob = GlobBuilder::new(&p).case_insensitive(true).build()?;
+                    Ok((glob.compile_matcher(), !p.contains('/')))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()
+        };
+
+        Ok(Self {
+            include: build(include)?,
+            exclude: build(exclude)?,
+        })
+    }
+
+    pub fn is_match(&self, entry_path: &str) -> bool {
+        let path = entry_path.replace('\\', "/");
+        let name = path.rsplit('/').next().unwrap_or_default();
+        let matches = |globs: &[(GlobMatcher, bool)]| {
+            globs
+                .iter()
+                .any(|(glob, name_only)| glob.is_match(if *name_only { name } else { &path }))
+        };
+
+        (self.include.is_empty() || matches(&self.include)) && !matches(&self.exclude)
+    }
+}
+
+/// Maps an entry path to a relative file system path, rejecting anything escaping `out_dir`.
+fn entry_out_path(
+    out_dir: &Path,
+    prefix: Option<&str>,
+    entry_path: &str,
+) -> anyhow::Result<PathBuf> {
+    let mut relative = PathBuf::new();
+    for part in prefix
+        .into_iter()
+        .flat_map(|p| p.split('\\'))
+        .chain(entry_path.split('\\'))
+        .filter(|p| !p.is_empty())
+    {
+        let part = Path::new(part);
+        if !matches!(
+            part.components().collect::<Vec<_>>().as_slice(),
+            [Component::Normal(_)]
+        ) {
+            return Err(PboError::new(
+                PboErrorKindCxx::InvalidArgument,
+                entry_path,
+                "Entry path escapes the output directory",
+            )
+            .into());
+        }
+        relative.push(part);
+    }
+
+    Ok(out_dir.join(relative))
+}
+
+fn extract_entry<R: Read + Seek>(
+    reader: &mut R,
+    entry: &HeaderEntry,
+    out_path: &Path,
+) -> anyhow::Result<()> {
+    let file_error = |path: &Path, e: io::Error| PboError::file(&entry.filename, path, e);
+    let read_error =
+        |e: anyhow::Error| PboError::wrap(PboErrorKindCxx::Truncated, &entry.filename, e);
+
+    if let Some(parent) = out_path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| file_error(parent, e))?;
+    }
+
+    if entry.packing_method == PACKING_METHOD_COMPRESSED {
+        let data = read_entry_data(reader, entry).map_err(read_error)?;
+        std::fs::write(out_path, data).map_err(|e| file_error(out_path, e))?;
+        return Ok(());
+    }
+
+    reader
+        .seek(SeekFrom::Start(entry.offset))
+        .map_err(|e| read_error(e.into()))?;
+    let mut file = BufWriter::new(File::create(out_path).map_err(|e| file_error(out_path, e))?);
+    let size = u64::from(entry.data_size);
+    let mut data = reader.take(size);
+    let copied = io::copy(&mut data, &mut file).map_err(|e| file_error(out_path, e))?;
+    if copied != size {
+        return Err(PboError::new(
+            PboErrorKindCxx::Truncated,
+            &entry.filename,
+            "Entry data truncated",
+        )
+        .into());
+    }
+    file.flush().map_err(|e| file_error(out_path, e))?;
+    Ok(())
+}
+
+/// Extracts one entry to `out_path`, or below it at the entry path if `full_path` is set.
+pub(crate) fn extract_single_entry(
+    source: &PboSource,
+    header: &PboHeader,
+    entry_path: &str,
+    out_path: &Path,
+    full_path: bool,
+) -> anyhow::Result<()> {
+    let entry = header
+        .find_entry(entry_path)
+        .ok_or_else(|| PboError::new(PboErrorKindCxx::NotFound, entry_path, "Entry not found"))?;
+    let out_path = if full_path {
+        entry_out_path(out_path, None, &entry.filename)?
+    } else {
+        out_path.to_path_buf()
+    };
+
+    let mut reader = source
+        .open()
+        .map_err(|e| PboError::wrap(PboErrorKindCxx::Io, &entry.filename, e.into()))?;
+    extract_entry(&mut reader, entry, &out_path)
+}
+
+/// Extracts all entries accepted by `filter` using up to `threads` workers.
+/// Repeated entry paths are reported as failures, only the first one is extracted.
+pub(crate) fn extract_entries(
+    source: &PboSource,
+    header: &PboHeader,
+    out_dir: &Path,
+    filter: &EntryFilter,
+    keep_prefix: bool,
+    threads: u32,
+) -> anyhow::Result<Vec<ExtractResultCxx>> {
+    let prefix = header.get_property("prefix").filter(|_| keep_prefix);
+    let mut seen = HashSet::new();
+    let entries: Vec<_> = header
+        .entries
+        .iter()
+        .filter(|e| filter.is_match(&e.filename))
+        .map(|e| {
+            let first = seen.insert(normalize_entry_path(&e.filename).to_ascii_lowercase());
+            (e, first)
+        })
+        .collect();
+
+    let next = AtomicUsize::new(0);
+    let worker = || -> anyhow::Result<Vec<(usize, ExtractResultCxx)>> {
+        let mut reader = source
+            .open()
+            .map_err(|e| PboError::wrap(PboErrorKindCxx::Io, "", e.into()))?;
+        let mut results = Vec::new();
+        loop {
+            let index = next.fetch_add(1, Ordering::Relaxed);
+            let Some(&(entry, first)) = entries.get(index) else {
+                break;
+            };
+
+            let out_path = entry_out_path(out_dir, prefix, &entry.filename);
+            let result = if first {
+                out_path
+                    .as_ref()
+                    .map_err(|e| anyhow::anyhow!("{}", e))
+                    .and_then(|out_path| extract_entry(&mut reader, entry, out_path))
+            } else {
+                Err(PboError::new(
+                    PboErrorKindCxx::InvalidHeader,
+                    &entry.filename,
+                    "Duplicate entry path, only the first one is extracted",
+                )
+                .into())
+            };
+
+            results.push((
+                index,
+                ExtractResultCxx {
+                    entry_path: entry.filename.clone(),
+                    out_path: out_path
+                        .map(|p| p.to_string_lossy().into_owned())
+                        .unwrap_or_default(),
+                    success: result.is_ok(),
+                    error: result.err().map(|e| e.to_string()).unwrap_or_default(),
+                },
+            ));
+        }
+        Ok(results)
+    };
+
+    let threads = (threads.max(1) as usize).min(entries.len().max(1));
+    let mut results = if threads == 1 {
+        worker()?
+    } else {
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..threads).map(|_| scope.spawn(worker)).collect();
+            let mut results = Vec::new();
+            for handle in handles {
+                let worker_results = handle.join().map_err(|_| {
+                    PboError::new(PboErrorKindCxx::Other, "", "Extraction worker panicked")
+                })??;
+                results.extend(worker_results);
+            }
+            Ok::<_, anyhow::Error>(results)
+        })?
+    };
+
+    results.sort_by_key(|(index, _)| *index);
+    Ok(results.into_iter().map(|(_, r)| r).collect())
+}
+
+pub(crate) enum EntrySource {
+    Path(PathBuf),
+    Data(Vec<u8>),
+    /// Entry of an existing PBO, copied as stored.
+    Raw(PboSource, HeaderEntry),
+}
+
+impl EntrySource {
+    fn size(&self) -> anyhow::Result<u32> {
+        let size = match self {
+            EntrySource::Path(path) => std::fs::metadata(path)
+                .map_err(|e| PboError::file("", path, e))?
+                .len(),
+            EntrySource::Data(data) => data.len() as u64,
+            EntrySource::Raw(_, entry) => u64::from(entry.original_size),
+        };
+        u32::try_from(size).map_err(|_| {
+            PboError::new(
+                PboErrorKindCxx::InvalidArgument,
+                "",
+                format!("Entry too large ({} bytes)", size),
+            )
+            .into()
+        })
+    }
+}
+
+pub(crate) struct WriterEntry {
+    pub filename: String,
+    pub packing_method: u32,
+    pub timestamp: u32,
+    pub source: EntrySource,
+}
+
+pub(crate) fn normalize_entry_path(entry_path: &str) -> String {
+    entry_path.replace('/', "\\")
+}
+
+pub(crate) fn mime_from_packing_method(packing_method: u32) -> String {
+    if packing_method == PACKING_METHOD_UNCOMPRESSED {
+        String::new()
+    } else {
+        String::from_utf8_lossy(&packing_method.to_be_bytes()).into_owned()
+    }
+}
+
+/// Maps a writer mime type to its packing method; only `""` and `"Cprs"` are supported.
+pub(crate) fn packing_method_from_mime(mime_type: &str) -> anyhow::Result<u32> {
+    match mime_type {
+        "" => Ok(PACKING_METHOD_UNCOMPRESSED),
+        "Cprs" => Ok(PACKING_METHOD_COMPRESSED),
+        _ => Err(PboError::new(
+            PboErrorKindCxx::InvalidArgument,
+            "",
+            format!("Unsupported mime type '{}'", mime_type),
+        )
+        .into()),
+    }
+}
+
+/// Header values of a `WriterEntry`, compressing `Cprs` entries ahead of writing.
+struct PreparedEntry {
+    packing_method: u32,
+    original_size: u32,
+    data_size: u32,
+    packed: Option<Vec<u8>>,
+}
+
+impl PreparedEntry {
+    /// Entries that don't get smaller when compressed are stored uncompressed.
+    fn new(entry: &WriterEntry) -> anyhow::Result<Self> {
+        if let EntrySource::Raw(_, raw) = &entry.source {
+            return Ok(Self {
+                packing_method: raw.packing_method,
+                original_size: raw.original_size,
+                data_size: raw.data_size,
+                packed: None,
+            });
+        }
+
+        let original_size = entry
+            .source
+            .size()
+            .map_err(|e| PboError::wrap(PboErrorKindCxx::Io, &entry.filename, e))?;
+        if entry.packing_method == PACKING_METHOD_COMPRESSED {
+            let packed = match &entry.source {
+                EntrySource::Path(path) => compress_lzss(
+                    &std::fs::read(path).map_err(|e| PboError::file(&entry.filename, path, e))?,
+                ),
+                EntrySource::Data(data) => compress_lzss(data),
+                EntrySource::Raw(..) => unreachable!(),
+            };
+            if packed.len() < original_size as usize {
+                return Ok(Self {
+                    packing_method: PACKING_METHOD_COMPRESSED,
+                    original_size,
+                    data_size: packed.len() as u32,
+                    packed: Some(packed),
+                });
+            }
+        }
+
+        let packing_method = match entry.packing_method {
+            PACKING_METHOD_COMPRESSED => PACKING_METHOD_UNCOMPRESSED,
+            method => method,
+        };
+        Ok(Self {
+            packing_method,
+            original_size,
+            data_size: original_size,
+            packed: None,
+        })
+    }
+}
+
+struct HashWriter<W> {
+    inner: W,
+    hasher: Sha1,
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn write_cstring<W: Write>(writer: &mut W, s: &str) -> io::Result<()> {
+    writer.write_all(s.as_bytes())?;
+    writer.write_all(&[0])
+}
+
+fn write_header_entry<W: Write>(
+    writer: &mut W,
+    filename: &str,
+    packing_method: u32,
+    original_size: u32,
+    timestamp: u32,
+    data_size: u32,
+) -> io::Result<()> {
+    write_cstring(writer, filename)?;
+    for value in [packing_method, original_size, 0, timestamp, data_size] {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Writes a complete PBO (header, data and SHA1 trailer) and returns the hash.
+pub(crate) fn write_pbo<W: Write>(
+    writer: W,
+    properties: &[(String, String)],
+    entries: &[WriterEntry],
+) -> anyhow::Result<Vec<u8>> {
+    let mut writer = HashWriter {
+        inner: writer,
+        hasher: Sha1::new(),
+    };
+
+    write_header_entry(&mut writer, "", PACKING_METHOD_VERSION, 0, 0, 0)?;
+    for (key, value) in properties {
+        write_cstring(&mut writer, key)?;
+        write_cstring(&mut writer, value)?;
+    }
+    writer.write_all(&[0])?;
+
+    let prepared = entries
+        .iter()
+        .map(PreparedEntry::new)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    for (entry, prepared) in entries.iter().zip(&prepared) {
+        write_header_entry(
+            &mut writer,
+            &entry.filename,
+            prepared.packing_method,
+            prepared.original_size,
+            entry.timestamp,
+            prepared.data_size,
+        )?;
+    }
+    write_header_entry(&mut writer, "", 0, 0, 0, 0)?;
+
+    // Raw entries usually come from a few source PBOs, each one is only opened once.
+    let mut raw_readers: Vec<(&PboSource, Box<dyn ReadSeek>)> = Vec::new();
+    for (entry, prepared) in entries.iter().zip(&prepared) {
+        let size = prepared.data_size;
+        match (&entry.source, &prepared.packed) {
+            (_, Some(packed)) => writer.write_all(packed)?,
+            (EntrySource::Path(path), None) => {
+                let file =
+                    File::open(path).map_err(|e| PboError::file(&entry.filename, path, e))?;
+                let copied = io::copy(&mut file.take(u64::from(size)), &mut writer)?;
+                if copied != u64::from(size) {
+                    return Err(PboError::new(
+                        PboErrorKindCxx::Truncated,
+                        &entry.filename,
+                        format!("File {} changed while writing", path.display()),
+                    )
+                    .into());
+                }
+            }
+            (EntrySource::Data(data), None) => writer.write_all(data)?,
+            (EntrySource::Raw(source, raw), None) => {
+                let read_error = |e: io::Error| {
+                    PboError::wrap(PboErrorKindCxx::Truncated, &raw.filename, e.into())
+                };
+                let index = match raw_readers.iter().position(|(s, _)| s.same(source)) {
+                    Some(index) => index,
+                    None => {
+                        raw_readers.push((source, source.open().map_err(read_error)?));
+                        raw_readers.len() - 1
+                    }
+                };
+                let reader = &mut raw_readers[index].1;
+                reader
+                    .seek(SeekFrom::Start(raw.offset))
+                    .map_err(read_error)?;
+                let copied = io::copy(&mut reader.take(u64::from(size)), &mut writer)?;
+                if copied != u64::from(size) {
+                    return Err(PboError::new(
+                        PboErrorKindCxx::Truncated,
+                        &raw.filename,
+                        "Entry data truncated",
+                    )
+                    .into());
+                }
+            }
         }
     }
+
+    let hash = writer.hasher.finalize().to_vec();
+    let mut writer = writer.inner;
+    writer.write_all(&[0])?;
+    writer.write_all(&hash)?;
+    writer.flush()?;
+
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(data: &[u8]) {
+        let compressed = compress_lzss(data);
+        let decompressed =
+            rvff::core::decompress_lzss_unk_size(&mut Cursor::new(compressed)).unwrap();
+        assert_eq!(decompressed, data);
+    }
+
+    fn noise(len: usize, mut seed: u32) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                seed as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lzss_roundtrip_empty() {
+        roundtrip(&[]);
+    }
+
+    #[test]
+    fn lzss_roundtrip_no_repeats() {
+        roundtrip(&(0..=255).collect::<Vec<u8>>());
+        roundtrip(&[1, 2]);
+    }
+
+    #[test]
+    fn lzss_roundtrip_long_runs() {
+        roundtrip(&[b'a'; 5000]);
+        let mut data = b"ab".repeat(1000);
+        data.extend([0; 17]);
+        roundtrip(&data);
+    }
+
+    #[test]
+    fn lzss_roundtrip_outside_window() {
+        let block = noise(64, 1);
+        let mut data = block.clone();
+        data.extend(noise(0x1800, 2));
+        data.extend(&block);
+        data.extend(noise(0x0FF0 - block.len(), 3));
+        data.extend(&block);
+        roundtrip(&data);
+    }
 }
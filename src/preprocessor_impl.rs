@@ -0,0 +1,658 @@
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{pbo_impl, vfs_impl::VfsCxx, PboReaderCxx};
+
+const MAX_INCLUDE_DEPTH: usize = 32;
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+/// Looks up `#include` targets.
+pub(crate) trait IncludeResolver {
+    /// Returns the resolved file name (used for nested includes) and its content.
+    fn resolve(&self, from: &str, include: &str) -> anyhow::Result<(String, String)>;
+}
+
+pub(crate) fn decode_text(buf: Vec<u8>) -> String {
+    let text = String::from_utf8(buf)
+        .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned());
+    match text.strip_prefix('\u{feff}') {
+        Some(text) => text.to_string(),
+        None => text,
+    }
+}
+
+/// Resolves includes relative to the including file on disk.
+///
+/// `\`-rooted includes are game paths, they are resolved relative to `root` (like a `P:` drive)
+/// and rejected without one.
+pub(crate) struct FsIncludeResolver {
+    pub root: Option<PathBuf>,
+}
+
+/// Applies `.` and `..` components, never going above `base`.
+fn join_normalized(base: &Path, relative: &str) -> PathBuf {
+    let mut path = base.to_path_buf();
+    let base_len = base.components().count();
+    for component in Path::new(relative).components() {
+        match component {
+            Component::ParentDir if path.components().count() > base_len => {
+                path.pop();
+            }
+            Component::Normal(part) => path.push(part),
+            _ => {}
+        }
+    }
+    path
+}
+
+impl IncludeResolver for FsIncludeResolver {
+    fn resolve(&self, from: &str, include: &str) -> anyhow::Result<(String, String)> {
+        let include = include.replace('\\', "/");
+        let path = match include.strip_prefix('/') {
+            Some(game_path) => {
+                let root = self.root.as_deref().ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Config: Can't include game path {} without an include root",
+                        include
+                    )
+                })?;
+                join_normalized(root, game_path)
+            }
+            None => {
+                let from = Path::new(from);
+                let mut path = from.parent().unwrap_or(Path::new("")).to_path_buf();
+                for component in Path::new(&include).components() {
+                    match component {
+                        Component::ParentDir => {
+                            path.pop();
+                        }
+                        Component::Normal(part) => path.push(part),
+                        _ => {}
+                    }
+                }
+                path
+            }
+        };
+
+        let content = std::fs::read(&path)
+            .map_err(|e| anyhow::anyhow!("Config: Can't include {}: {}", path.display(), e))?;
+        Ok((path.to_string_lossy().into_owned(), decode_text(content)))
+    }
+}
+
+/// Rejects every include, for configs parsed from memory.
+pub(crate) struct NoIncludeResolver;
+
+impl IncludeResolver for NoIncludeResolver {
+    fn resolve(&self, _from: &str, include: &str) -> anyhow::Result<(String, String)> {
+        Err(anyhow::anyhow!(
+            "Config: Can't include {} without an include resolver",
+            include
+        ))
+    }
+}
+
+/// Joins an include to the game path of the including file, `\`-rooted includes are absolute.
+fn include_game_path(from: &str, include: &str) -> String {
+    let include = include.replace('/', "\\");
+    let mut parts: Vec<&str> = if include.starts_with('\\') {
+        Vec::new()
+    } else {
+        let mut parts: Vec<&str> = from.split('\\').filter(|p| !p.is_empty()).collect();
+        parts.pop();
+        parts
+    };
+    for part in include.split('\\') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("\\")
+}
+
+/// Resolves `\prefix\path` includes through the VFS, other paths relative to the including file.
+impl IncludeResolver for VfsCxx {
+    fn resolve(&self, from: &str, include: &str) -> anyhow::Result<(String, String)> {
+        let path = include_game_path(from, include);
+        Ok((path.clone(), decode_text(self.read_path(&path)?)))
+    }
+}
+
+/// Resolves includes to entries of the PBO, file names are game paths starting with the prefix.
+impl IncludeResolver for PboReaderCxx {
+    fn resolve(&self, from: &str, include: &str) -> anyhow::Result<(String, String)> {
+        let path = include_game_path(from, include);
+        let prefix = self.header.get_property("prefix").unwrap_or_default();
+        let prefix = prefix.trim_matches('\\');
+        let entry_path = if prefix.is_empty() {
+            Some(path.as_str())
+        } else {
+            path.get(..prefix.len())
+                .filter(|p| p.eq_ignore_ascii_case(prefix))
+                .and_then(|_| path[prefix.len()..].strip_prefix('\\'))
+        };
+        let entry = entry_path
+            .and_then(|p| self.header.find_entry(p))
+            .ok_or_else(|| {
+                anyhow::anyhow!("Config: Can't include {}, not found in the PBO", path)
+            })?;
+        let data = pbo_impl::read_entry_data(&mut self.source.open()?, entry)?;
+        Ok((path, decode_text(data)))
+    }
+}
+
+struct Macro {
+    params: Option<Vec<String>>,
+    body: String,
+}
+
+/// Preprocessed text plus the source file and line of every output line.
+pub(crate) struct Preprocessed {
+    pub text: String,
+    pub lines: Vec<(String, usize)>,
+}
+
+impl Preprocessed {
+    pub fn location(&self, line: usize) -> String {
+        match self.lines.get(line) {
+            Some((file, line)) => format!("{}:{}", file, line),
+            None => format!("line {}", line + 1),
+        }
+    }
+}
+
+struct Conditional {
+    active: bool,
+    parent_active: bool,
+    in_else: bool,
+}
+
+/// Handles `#define`, `#undef`, `#include`, `#ifdef`, `#ifndef`, `#if`, `#else` and `#endif`.
+///
+/// Like in the game, `#if` conditions have to expand to an integer and there is no `#elif`.
+pub(crate) struct Preprocessor<'a> {
+    resolver: &'a dyn IncludeResolver,
+    macros: HashMap<String, Macro>,
+    out: Preprocessed,
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Length of the string literal starting at `chars[start]`, `""` being an escaped quote.
+fn string_literal_len(chars: &[char], start: usize) -> usize {
+    let mut i = start + 1;
+    while i < chars.len() {
+        if chars[i] == '"' {
+            if chars.get(i + 1) == Some(&'"') {
+                i += 2;
+                continue;
+            }
+            return i + 1 - start;
+        }
+        i += 1;
+    }
+    chars.len() - start
+}
+
+/// Removes comments outside of string literals, keeping line breaks.
+fn strip_comments(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        match (chars[i], chars.get(i + 1)) {
+            ('"', _) => {
+                let len = string_literal_len(&chars, i);
+                out.extend(&chars[i..i + len]);
+                i += len;
+            }
+            ('/', Some('/')) => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            }
+            ('/', Some('*')) => {
+                i += 2;
+                while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                    if chars[i] == '\n' {
+                        out.push('\n');
+                    }
+                    i += 1;
+                }
+                i += 2;
+            }
+            (c, _) => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Splits macro arguments at top level commas, starting after the opening parenthesis.
+/// Returns the arguments and the index after the closing parenthesis.
+fn parse_macro_args(chars: &[char], start: usize) -> Option<(Vec<String>, usize)> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '"' => {
+                let len = string_literal_len(chars, i);
+                current.extend(&chars[i..i + len]);
+                i += len;
+                continue;
+            }
+            '(' | '[' | '{' => depth += 1,
+            ')' if depth == 0 => {
+                args.push(current.trim().to_string());
+                return Some((args, i + 1));
+            }
+            ')' | ']' | '}' => depth -= 1,
+            ',' if depth == 0 => {
+                args.push(current.trim().to_string());
+                current.clear();
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+        current.push(chars[i]);
+        i += 1;
+    }
+    None
+}
+
+enum BodyToken {
+    Ident(String),
+    Stringify,
+    Concat,
+    Space(String),
+    Other(String),
+}
+
+fn tokenize_body(body: &str) -> Vec<BodyToken> {
+    let chars: Vec<char> = body.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if is_ident_start(c) {
+            while i < chars.len() && is_ident_char(chars[i]) {
+                i += 1;
+            }
+            tokens.push(BodyToken::Ident(chars[start..i].iter().collect()));
+        } else if c == '#' && chars.get(i + 1) == Some(&'#') {
+            tokens.push(BodyToken::Concat);
+            i += 2;
+        } else if c == '#' {
+            tokens.push(BodyToken::Stringify);
+            i += 1;
+        } else if c.is_whitespace() {
+            while i < chars.len() && chars[i].is_whitespace() {
+                i += 1;
+            }
+            tokens.push(BodyToken::Space(chars[start..i].iter().collect()));
+        } else if c == '"' {
+            i += string_literal_len(&chars, i);
+            tokens.push(BodyToken::Other(chars[start..i].iter().collect()));
+        } else {
+            i += 1;
+            while i < chars.len() && is_ident_char(chars[i]) && !is_ident_start(chars[start]) {
+                i += 1;
+            }
+            tokens.push(BodyToken::Other(chars[start..i].iter().collect()));
+        }
+    }
+    tokens
+}
+
+impl<'a> Preprocessor<'a> {
+    pub fn new(resolver: &'a dyn IncludeResolver) -> Self {
+        Self {
+            resolver,
+            macros: HashMap::new(),
+            out: Preprocessed {
+                text: String::new(),
+                lines: Vec::new(),
+            },
+        }
+    }
+
+    pub fn run(mut self, file: &str, content: &str) -> anyhow::Result<Preprocessed> {
+        self.process_file(file, content, 0)?;
+        Ok(self.out)
+    }
+
+    fn process_file(&mut self, file: &str, content: &str, depth: usize) -> anyhow::Result<()> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(anyhow::anyhow!(
+                "Config: {}: Includes nested too deep",
+                file
+            ));
+        }
+
+        let content = strip_comments(content);
+        let mut conditionals: Vec<Conditional> = Vec::new();
+        let mut lines = content.lines().enumerate();
+        while let Some((index, line)) = lines.next() {
+            let mut line = line.to_string();
+            while line.ends_with('\\') {
+                line.pop();
+                match lines.next() {
+                    Some((_, next)) => {
+                        line.push('\n');
+                        line.push_str(next);
+                    }
+                    None => break,
+                }
+            }
+
+            let location = || format!("{}:{}", file, index + 1);
+            let active = conditionals.last().is_none_or(|c| c.active);
+            let trimmed = line.trim_start();
+            let Some(directive) = trimmed.strip_prefix('#') else {
+                if active {
+                    let expanded = self.expand(&line, &mut Vec::new(), 0)?;
+                    self.push_line(&expanded, file, index + 1);
+                }
+                continue;
+            };
+
+            let directive = directive.trim_start();
+            let name_len = directive
+                .find(|c: char| !is_ident_char(c))
+                .unwrap_or(directive.len());
+            let (name, rest) = directive.split_at(name_len);
+            let rest = rest.trim();
+
+            match name {
+                "ifdef" | "ifndef" => {
+                    let defined = self.macros.contains_key(rest);
+                    conditionals.push(Conditional {
+                        active: active && (defined == (name == "ifdef")),
+                        parent_active: active,
+                        in_else: false,
+                    });
+                }
+                "if" => {
+                    let condition = active
+                        && self
+                            .condition(rest)
+                            .map_err(|e| anyhow::anyhow!("Config: {}: {}", location(), e))?;
+                    conditionals.push(Conditional {
+                        active: condition,
+                        parent_active: active,
+                        in_else: false,
+                    });
+                }
+                "else" => match conditionals.last_mut() {
+                    Some(c) if !c.in_else => {
+                        c.in_else = true;
+                        c.active = c.parent_active && !c.active;
+                    }
+                    _ => return Err(anyhow::anyhow!("Config: {}: Unexpected #else", location())),
+                },
+                "endif" => {
+                    if conditionals.pop().is_none() {
+                        return Err(anyhow::anyhow!("Config: {}: Unexpected #endif", location()));
+                    }
+                }
+                _ if !active => {}
+                "define" => self
+                    .define(rest)
+                    .map_err(|e| anyhow::anyhow!("Config: {}: {}", location(), e))?,
+                "undef" => {
+                    self.macros.remove(rest);
+                }
+                "include" => {
+                    let path = rest
+                        .strip_prefix('"')
+                        .and_then(|r| r.strip_suffix('"'))
+                        .or_else(|| rest.strip_prefix('<').and_then(|r| r.strip_suffix('>')))
+                        .ok_or_else(|| {
+                            anyhow::anyhow!("Config: {}: Invalid #include {}", location(), rest)
+                        })?;
+                    let (included, content) = self.resolver.resolve(file, path)?;
+                    self.process_file(&included, &content, depth + 1)?;
+                }
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "Config: {}: Unsupported directive #{}",
+                        location(),
+                        name
+                    ))
+                }
+            }
+        }
+
+        if !conditionals.is_empty() {
+            return Err(anyhow::anyhow!("Config: {}: Missing #endif", file));
+        }
+        Ok(())
+    }
+
+    /// Evaluates an `#if` condition, which has to expand to an integer like the game's `#if`.
+    fn condition(&self, condition: &str) -> anyhow::Result<bool> {
+        let expanded = self.expand(condition, &mut Vec::new(), 0)?;
+        let value = expanded.trim().parse::<i64>().map_err(|_| {
+            anyhow::anyhow!(
+                "Unsupported #if condition '{}', only integers are supported",
+                expanded.trim()
+            )
+        })?;
+        Ok(value != 0)
+    }
+
+    fn push_line(&mut self, line: &str, file: &str, number: usize) {
+        for part in line.split('\n') {
+            self.out.text.push_str(part);
+            self.out.text.push('\n');
+            self.out.lines.push((file.to_string(), number));
+        }
+    }
+
+    fn define(&mut self, definition: &str) -> anyhow::Result<()> {
+        let name_len = definition
+            .find(|c: char| !is_ident_char(c))
+            .unwrap_or(definition.len());
+        let (name, rest) = definition.split_at(name_len);
+        if name.is_empty() || !name.starts_with(is_ident_start) {
+            return Err(anyhow::anyhow!("Invalid #define {}", definition));
+        }
+
+        let (params, body) = match rest.strip_prefix('(') {
+            Some(rest) => {
+                let end = rest
+                    .find(')')
+                    .ok_or_else(|| anyhow::anyhow!("Unclosed parameters of {}", name))?;
+                let params = rest[..end]
+                    .split(',')
+                    .map(|p| p.trim().to_string())
+                    .filter(|p| !p.is_empty())
+                    .collect();
+                (Some(params), &rest[end + 1..])
+            }
+            None => (None, rest),
+        };
+
+        self.macros.insert(
+            name.to_string(),
+            Macro {
+                params,
+                body: body.trim().replace('\n', " "),
+            },
+        );
+        Ok(())
+    }
+
+    /// Expands macros outside of string literals, `disabled` guards against recursion.
+    fn expand(
+        &self,
+        text: &str,
+        disabled: &mut Vec<String>,
+        depth: usize,
+    ) -> anyhow::Result<String> {
+        if depth > MAX_EXPANSION_DEPTH {
+            return Err(anyhow::anyhow!("Config: Macro expansion nested too deep"));
+        }
+
+        let chars: Vec<char> = text.chars().collect();
+        let mut out = String::with_capacity(text.len());
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            if c == '"' {
+                let len = string_literal_len(&chars, i);
+                out.extend(&chars[i..i + len]);
+                i += len;
+                continue;
+            }
+            if c.is_ascii_digit() {
+                while i < chars.len() && (is_ident_char(chars[i]) || chars[i] == '.') {
+                    out.push(chars[i]);
+                    i += 1;
+                }
+                continue;
+            }
+            if !is_ident_start(c) {
+                out.push(c);
+                i += 1;
+                continue;
+            }
+
+            let start = i;
+            while i < chars.len() && is_ident_char(chars[i]) {
+                i += 1;
+            }
+            let name: String = chars[start..i].iter().collect();
+            let Some(macro_def) = self.macros.get(&name).filter(|_| !disabled.contains(&name))
+            else {
+                out.push_str(&name);
+                continue;
+            };
+
+            let replaced = match &macro_def.params {
+                None => macro_def.body.clone(),
+                Some(params) => {
+                    let mut open = i;
+                    while open < chars.len() && chars[open].is_whitespace() {
+                        open += 1;
+                    }
+                    if chars.get(open) != Some(&'(') {
+                        out.push_str(&name);
+                        continue;
+                    }
+                    let (mut args, end) = parse_macro_args(&chars, open + 1)
+                        .ok_or_else(|| anyhow::anyhow!("Config: Unclosed arguments of {}", name))?;
+                    i = end;
+                    if params.is_empty() && args.len() == 1 && args[0].is_empty() {
+                        args.clear();
+                    }
+                    if args.len() != params.len() {
+                        return Err(anyhow::anyhow!(
+                            "Config: Macro {} expects {} arguments, got {}",
+                            name,
+                            params.len(),
+                            args.len()
+                        ));
+                    }
+                    self.substitute(&macro_def.body, params, &args, disabled, depth)?
+                }
+            };
+
+            disabled.push(name);
+            let expanded = self.expand(&replaced, disabled, depth + 1);
+            disabled.pop();
+            out.push_str(&expanded?);
+        }
+        Ok(out)
+    }
+
+    /// Replaces parameters in a macro body, handling `#param` and `a##b`.
+    fn substitute(
+        &self,
+        body: &str,
+        params: &[String],
+        args: &[String],
+        disabled: &mut Vec<String>,
+        depth: usize,
+    ) -> anyhow::Result<String> {
+        let tokens = tokenize_body(body);
+        let arg = |name: &str| params.iter().position(|p| p == name).map(|i| &args[i]);
+        let next_significant = |from: usize| {
+            tokens[from..]
+                .iter()
+                .find(|t| !matches!(t, BodyToken::Space(_)))
+        };
+
+        let mut out = String::new();
+        let mut after_concat = false;
+        let mut i = 0;
+        while i < tokens.len() {
+            match &tokens[i] {
+                BodyToken::Concat => {
+                    out.truncate(out.trim_end().len());
+                    after_concat = true;
+                    i += 1;
+                    continue;
+                }
+                BodyToken::Space(_) if after_concat => {
+                    i += 1;
+                    continue;
+                }
+                BodyToken::Space(space) => out.push_str(space),
+                BodyToken::Stringify => {
+                    let mut next = i + 1;
+                    while matches!(tokens.get(next), Some(BodyToken::Space(_))) {
+                        next += 1;
+                    }
+                    let value = match tokens.get(next) {
+                        Some(BodyToken::Ident(name)) => arg(name),
+                        _ => None,
+                    };
+                    match value {
+                        Some(value) => {
+                            out.push('"');
+                            out.push_str(&value.replace('"', "\"\""));
+                            out.push('"');
+                            i = next;
+                        }
+                        None => out.push('#'),
+                    }
+                }
+                BodyToken::Ident(name) => match arg(name) {
+                    Some(value) => {
+                        let before_concat =
+                            matches!(next_significant(i + 1), Some(BodyToken::Concat));
+                        if after_concat || before_concat {
+                            out.push_str(value);
+                        } else {
+                            out.push_str(&self.expand(value, disabled, depth + 1)?);
+                        }
+                    }
+                    None => out.push_str(name),
+                },
+                BodyToken::Other(other) => out.push_str(other),
+            }
+            after_concat = false;
+            i += 1;
+        }
+        Ok(out)
+    }
+}
@@ -0,0 +1,518 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Cursor, Read, Seek, SeekFrom},
+};
+
+use cxx::CxxString;
+use rsa::{
+    rand_core::OsRng,
+    traits::{PrivateKeyParts, PublicKeyParts},
+    BigUint, Pkcs1v15Sign, RsaPrivateKey, RsaPublicKey,
+};
+use sha1::{Digest, Sha1};
+
+use crate::{
+    bridge::{BiKeyCxx, BiSignCxx, BiSignVersionCxx, PboErrorKindCxx},
+    pbo_impl::{self, read_cstring, read_u32, PboError},
+};
+
+const PUBLICKEYBLOB: u8 = 0x06;
+const PRIVATEKEYBLOB: u8 = 0x07;
+const CALG_RSA_SIGN: u32 = 0x0000_2400;
+/// Smallest key length in bits accepted when generating keys.
+const MIN_KEY_LENGTH: u32 = 1024;
+/// Largest key length in bits accepted when generating or reading keys and signatures.
+const MAX_KEY_LENGTH: u32 = 4096;
+
+const V2_EXCLUDED_EXTENSIONS: [&str; 13] = [
+    "paa", "jpg", "p3d", "tga", "rvmat", "lip", "ogg", "wss", "png", "rtm", "pac", "fxy", "wrp",
+];
+const V3_INCLUDED_EXTENSIONS: [&str; 11] = [
+    "sqf", "inc", "bikb", "ext", "fsm", "sqm", "hpp", "cfg", "sqs", "h", "sqfc",
+];
+
+struct KeyBlob {
+    length: u32,
+    exponent: u32,
+    modulus: Vec<u8>,
+}
+
+/// Reads `len` bytes, growing the buffer with the input instead of trusting `len` up front.
+fn read_bytes<R: Read>(reader: &mut R, len: u32) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    reader.take(u64::from(len)).read_to_end(&mut buf)?;
+    if buf.len() != len as usize {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(buf)
+}
+
+/// Reads the common part of a CryptoAPI key blob and returns (blob size, length, exponent).
+fn read_blob_header<R: Read>(
+    reader: &mut R,
+    blob_type: u8,
+    magic: &[u8; 4],
+) -> anyhow::Result<(u32, u32, u32)> {
+    let blob_size = read_u32(reader)?;
+
+    let mut header = [0_u8; 4];
+    reader.read_exact(&mut header)?;
+    let alg = read_u32(reader)?;
+    if header[0] != blob_type || alg != CALG_RSA_SIGN {
+        return Err(anyhow::anyhow!("Signature: Unsupported key blob"));
+    }
+
+    let mut blob_magic = [0_u8; 4];
+    reader.read_exact(&mut blob_magic)?;
+    if &blob_magic != magic {
+        return Err(anyhow::anyhow!("Signature: Invalid key magic"));
+    }
+
+    let length = read_u32(reader)?;
+    let exponent = read_u32(reader)?;
+    if length == 0 || length > MAX_KEY_LENGTH || !length.is_multiple_of(16) {
+        return Err(anyhow::anyhow!("Signature: Invalid key length {}", length));
+    }
+
+    Ok((blob_size, length, exponent))
+}
+
+fn read_public_key_blob<R: Read>(reader: &mut R) -> anyhow::Result<KeyBlob> {
+    let (blob_size, length, exponent) = read_blob_header(reader, PUBLICKEYBLOB, b"RSA1")?;
+    let modulus = read_bytes(reader, length / 8)?;
+
+    if blob_size != 20 + length / 8 {
+        return Err(anyhow::anyhow!(
+            "Signature: Key blob size {} doesn't match key length {}",
+            blob_size,
+            length
+        ));
+    }
+
+    Ok(KeyBlob {
+        length,
+        exponent,
+        modulus,
+    })
+}
+
+fn write_public_key_blob(buf: &mut Vec<u8>, length: u32, exponent: u32, modulus: &[u8]) {
+    buf.extend_from_slice(&(20 + length / 8).to_le_bytes());
+    buf.extend_from_slice(&[PUBLICKEYBLOB, 0x02, 0, 0]);
+    buf.extend_from_slice(&CALG_RSA_SIGN.to_le_bytes());
+    buf.extend_from_slice(b"RSA1");
+    buf.extend_from_slice(&length.to_le_bytes());
+    buf.extend_from_slice(&exponent.to_le_bytes());
+    buf.extend_from_slice(modulus);
+}
+
+fn write_cstring(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(s.as_bytes());
+    buf.push(0);
+}
+
+fn write_sized_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+fn to_le_padded(value: &BigUint, len: u32) -> Vec<u8> {
+    let mut bytes = value.to_bytes_le();
+    bytes.resize(len as usize, 0);
+    bytes
+}
+
+fn read_bikey<R: Read>(reader: &mut R) -> anyhow::Result<BiKeyCxx> {
+    let authority = read_cstring(reader)?;
+    let blob = read_public_key_blob(reader)?;
+
+    Ok(BiKeyCxx {
+        authority,
+        length: blob.length,
+        exponent: blob.exponent,
+        modulus: blob.modulus,
+    })
+}
+
+fn read_bisign<R: Read>(reader: &mut R) -> anyhow::Result<BiSignCxx> {
+    let authority = read_cstring(reader)?;
+    let blob = read_public_key_blob(reader)?;
+
+    let read_signature = |reader: &mut R| -> anyhow::Result<Vec<u8>> {
+        let len = read_u32(reader)?;
+        if len != blob.length / 8 {
+            return Err(anyhow::anyhow!(
+                "Signature: Signature length {} doesn't match key length {}",
+                len,
+                blob.length
+            ));
+        }
+        Ok(read_bytes(reader, len)?)
+    };
+
+    let sig1 = read_signature(reader)?;
+    let version = match read_u32(reader)? {
+        2 => BiSignVersionCxx::V2,
+        3 => BiSignVersionCxx::V3,
+        v => return Err(anyhow::anyhow!("Signature: Unknown version {}", v)),
+    };
+    let sig2 = read_signature(reader)?;
+    let sig3 = read_signature(reader)?;
+
+    Ok(BiSignCxx {
+        authority,
+        version,
+        length: blob.length,
+        exponent: blob.exponent,
+        modulus: blob.modulus,
+        sig1,
+        sig2,
+        sig3,
+    })
+}
+
+fn bikey_to_vec(key: &BiKeyCxx) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_cstring(&mut buf, &key.authority);
+    write_public_key_blob(&mut buf, key.length, key.exponent, &key.modulus);
+    buf
+}
+
+fn bisign_to_vec(sign: &BiSignCxx) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_cstring(&mut buf, &sign.authority);
+    write_public_key_blob(&mut buf, sign.length, sign.exponent, &sign.modulus);
+    write_sized_bytes(&mut buf, &sign.sig1);
+    buf.extend_from_slice(&(sign.version.repr as u32).to_le_bytes());
+    write_sized_bytes(&mut buf, &sign.sig2);
+    write_sized_bytes(&mut buf, &sign.sig3);
+    buf
+}
+
+pub fn create_bikey_path(path: &CxxString) -> anyhow::Result<BiKeyCxx> {
+    let file = File::open(path.to_string())?;
+    read_bikey(&mut BufReader::new(file))
+}
+
+pub fn create_bikey_vec(buf: &Vec<u8>) -> anyhow::Result<BiKeyCxx> {
+    read_bikey(&mut Cursor::new(buf))
+}
+
+pub fn create_bisign_path(path: &CxxString) -> anyhow::Result<BiSignCxx> {
+    let file = File::open(path.to_string())?;
+    read_bisign(&mut BufReader::new(file))
+}
+
+pub fn create_bisign_vec(buf: &Vec<u8>) -> anyhow::Result<BiSignCxx> {
+    read_bisign(&mut Cursor::new(buf))
+}
+
+pub fn write_bikey_path(key: &BiKeyCxx, path: &CxxString) -> anyhow::Result<()> {
+    Ok(std::fs::write(path.to_string(), bikey_to_vec(key))?)
+}
+
+pub fn write_bikey_vec(key: &BiKeyCxx) -> Vec<u8> {
+    bikey_to_vec(key)
+}
+
+pub fn write_bisign_path(sign: &BiSignCxx, path: &CxxString) -> anyhow::Result<()> {
+    Ok(std::fs::write(path.to_string(), bisign_to_vec(sign))?)
+}
+
+pub fn write_bisign_vec(sign: &BiSignCxx) -> Vec<u8> {
+    bisign_to_vec(sign)
+}
+
+pub struct BiPrivateKeyCxx {
+    authority: String,
+    length: u32,
+    key: RsaPrivateKey,
+}
+
+impl BiPrivateKeyCxx {
+    fn exponent(&self) -> u32 {
+        let mut bytes = self.key.e().to_bytes_le();
+        bytes.resize(4, 0);
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    pub fn get_authority(&self) -> String {
+        self.authority.clone()
+    }
+
+    pub fn get_bikey(&self) -> BiKeyCxx {
+        BiKeyCxx {
+            authority: self.authority.clone(),
+            length: self.length,
+            exponent: self.exponent(),
+            modulus: to_le_padded(self.key.n(), self.length / 8),
+        }
+    }
+
+    pub fn write_to_vec(&self) -> anyhow::Result<Vec<u8>> {
+        let primes = self.key.primes();
+        let (dp, dq, qinv) = match (self.key.dp(), self.key.dq(), self.key.crt_coefficient()) {
+            (Some(dp), Some(dq), Some(qinv)) if primes.len() == 2 => (dp, dq, qinv),
+            _ => return Err(anyhow::anyhow!("Signature: Key has no CRT values")),
+        };
+
+        let half = self.length / 16;
+        let mut buf = Vec::new();
+        write_cstring(&mut buf, &self.authority);
+        buf.extend_from_slice(&(20 + half * 9).to_le_bytes());
+        buf.extend_from_slice(&[PRIVATEKEYBLOB, 0x02, 0, 0]);
+        buf.extend_from_slice(&CALG_RSA_SIGN.to_le_bytes());
+        buf.extend_from_slice(b"RSA2");
+        buf.extend_from_slice(&self.length.to_le_bytes());
+        buf.extend_from_slice(&self.exponent().to_le_bytes());
+        buf.extend(to_le_padded(self.key.n(), self.length / 8));
+        buf.extend(to_le_padded(&primes[0], half));
+        buf.extend(to_le_padded(&primes[1], half));
+        buf.extend(to_le_padded(dp, half));
+        buf.extend(to_le_padded(dq, half));
+        buf.extend(to_le_padded(&qinv, half));
+        buf.extend(to_le_padded(self.key.d(), self.length / 8));
+        Ok(buf)
+    }
+
+    pub fn write_to_path(&self, path: &CxxString) -> anyhow::Result<()> {
+        Ok(std::fs::write(path.to_string(), self.write_to_vec()?)?)
+    }
+}
+
+fn read_biprivatekey<R: Read>(reader: &mut R) -> anyhow::Result<Box<BiPrivateKeyCxx>> {
+    let authority = read_cstring(reader)?;
+    let (blob_size, length, exponent) = read_blob_header(reader, PRIVATEKEYBLOB, b"RSA2")?;
+    if blob_size != 20 + length / 16 * 9 {
+        return Err(anyhow::anyhow!(
+            "Signature: Key blob size {} doesn't match key length {}",
+            blob_size,
+            length
+        ));
+    }
+
+    let n = BigUint::from_bytes_le(&read_bytes(reader, length / 8)?);
+    let p = BigUint::from_bytes_le(&read_bytes(reader, length / 16)?);
+    let q = BigUint::from_bytes_le(&read_bytes(reader, length / 16)?);
+    // dp, dq and qinv are recomputed from the primes
+    read_bytes(reader, length / 16 * 3)?;
+    let d = BigUint::from_bytes_le(&read_bytes(reader, length / 8)?);
+
+    let key = RsaPrivateKey::from_components(n, BigUint::from(exponent), d, vec![p, q])?;
+    key.validate()?;
+
+    Ok(Box::new(BiPrivateKeyCxx {
+        authority,
+        length,
+        key,
+    }))
+}
+
+pub fn create_biprivatekey_path(path: &CxxString) -> anyhow::Result<Box<BiPrivateKeyCxx>> {
+    let file = File::open(path.to_string())?;
+    read_biprivatekey(&mut BufReader::new(file))
+}
+
+pub fn create_biprivatekey_vec(buf: &Vec<u8>) -> anyhow::Result<Box<BiPrivateKeyCxx>> {
+    read_biprivatekey(&mut Cursor::new(buf))
+}
+
+pub fn generate_biprivatekey(
+    authority: &CxxString,
+    length: u32,
+) -> anyhow::Result<Box<BiPrivateKeyCxx>> {
+    let authority = authority.to_string();
+    if authority.is_empty() || authority.contains('\0') {
+        return Err(anyhow::anyhow!(
+            "Signature: Invalid authority name '{}'",
+            authority
+        ));
+    }
+    if !(MIN_KEY_LENGTH..=MAX_KEY_LENGTH).contains(&length) || !length.is_multiple_of(16) {
+        return Err(anyhow::anyhow!(
+            "Signature: Invalid key length {}, expected {} to {} bits",
+            length,
+            MIN_KEY_LENGTH,
+            MAX_KEY_LENGTH
+        ));
+    }
+
+    let key = RsaPrivateKey::new(&mut OsRng, length as usize)?;
+
+    Ok(Box::new(BiPrivateKeyCxx {
+        authority,
+        length,
+        key,
+    }))
+}
+
+fn should_hash_file(version: BiSignVersionCxx, filename: &str) -> bool {
+    let ext = filename
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_lowercase())
+        .unwrap_or_default();
+
+    if version == BiSignVersionCxx::V2 {
+        !V2_EXCLUDED_EXTENSIONS.contains(&ext.as_str())
+    } else {
+        V3_INCLUDED_EXTENSIONS.contains(&ext.as_str())
+    }
+}
+
+/// Computes the three hashes that make up a BI signature of the given version.
+/// Fails with `Corrupt` if the stored SHA1 checksum is missing or doesn't match the data.
+fn signature_hashes<R: Read + Seek>(
+    reader: &mut R,
+    version: BiSignVersionCxx,
+) -> anyhow::Result<[Vec<u8>; 3]> {
+    let header = pbo_impl::read_header(reader)
+        .map_err(|e| PboError::wrap(PboErrorKindCxx::InvalidHeader, "", e))?;
+    let check = pbo_impl::check_header_hash(reader, &header)?;
+    if check.expected.is_empty() {
+        return Err(PboError::new(PboErrorKindCxx::Corrupt, "", "Missing SHA1 checksum").into());
+    }
+    if !check.is_valid {
+        return Err(PboError::new(PboErrorKindCxx::Corrupt, "", "SHA1 checksum mismatch").into());
+    }
+    let hash1 = check.computed;
+
+    let mut entries: Vec<_> = header.entries.iter().filter(|e| e.data_size > 0).collect();
+    entries.sort_by_cached_key(|e| e.filename.to_lowercase());
+
+    let mut name_hasher = Sha1::new();
+    let mut file_hasher = Sha1::new();
+    let mut nothing = true;
+    for entry in entries {
+        name_hasher.update(entry.filename.to_lowercase().as_bytes());
+
+        if should_hash_file(version, &entry.filename) {
+            reader.seek(SeekFrom::Start(entry.offset))?;
+            io::copy(
+                &mut reader.take(u64::from(entry.data_size)),
+                &mut file_hasher,
+            )?;
+            nothing = false;
+        }
+    }
+    if nothing {
+        file_hasher.update(if version == BiSignVersionCxx::V2 {
+            b"nothing"
+        } else {
+            b"gnihton"
+        });
+    }
+    let name_hash = name_hasher.finalize();
+    let file_hash = file_hasher.finalize();
+
+    let mut prefix = header.get_property("prefix").unwrap_or_default().to_owned();
+    if !prefix.is_empty() && !prefix.ends_with('\\') {
+        prefix.push('\\');
+    }
+
+    let mut hasher = Sha1::new();
+    hasher.update(&hash1);
+    hasher.update(name_hash);
+    hasher.update(prefix.as_bytes());
+    let hash2 = hasher.finalize().to_vec();
+
+    let mut hasher = Sha1::new();
+    hasher.update(file_hash);
+    hasher.update(name_hash);
+    hasher.update(prefix.as_bytes());
+    let hash3 = hasher.finalize().to_vec();
+
+    Ok([hash1, hash2, hash3])
+}
+
+fn unknown_version() -> anyhow::Error {
+    PboError::new(
+        PboErrorKindCxx::InvalidArgument,
+        "",
+        "Unknown signature version",
+    )
+    .into()
+}
+
+/// Checks all three signatures of `sign` for the PBO in `reader` against `key`.
+pub(crate) fn verify_signature<R: Read + Seek>(
+    reader: &mut R,
+    key: &BiKeyCxx,
+    sign: &BiSignCxx,
+) -> anyhow::Result<bool> {
+    if key.authority != sign.authority || key.modulus != sign.modulus {
+        return Ok(false);
+    }
+    if sign.version != BiSignVersionCxx::V2 && sign.version != BiSignVersionCxx::V3 {
+        return Err(unknown_version());
+    }
+
+    let public_key = RsaPublicKey::new(
+        BigUint::from_bytes_le(&key.modulus),
+        BigUint::from(key.exponent),
+    )
+    .map_err(|e| {
+        PboError::new(
+            PboErrorKindCxx::InvalidArgument,
+            "",
+            format!("Invalid key: {}", e),
+        )
+    })?;
+
+    let hashes = signature_hashes(reader, sign.version)?;
+    for (hash, sig) in hashes.iter().zip([&sign.sig1, &sign.sig2, &sign.sig3]) {
+        let sig: Vec<u8> = sig.iter().rev().copied().collect();
+        if public_key
+            .verify(Pkcs1v15Sign::new::<Sha1>(), hash, &sig)
+            .is_err()
+        {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Signs the PBO in `reader`, producing a `.bisign` of the given version.
+pub(crate) fn sign_pbo<R: Read + Seek>(
+    reader: &mut R,
+    key: &BiPrivateKeyCxx,
+    version: BiSignVersionCxx,
+) -> anyhow::Result<BiSignCxx> {
+    if version != BiSignVersionCxx::V2 && version != BiSignVersionCxx::V3 {
+        return Err(unknown_version());
+    }
+
+    let [hash1, hash2, hash3] = signature_hashes(reader, version)?;
+    let sign = |hash: &[u8]| -> anyhow::Result<Vec<u8>> {
+        let mut sig = key
+            .key
+            .sign(Pkcs1v15Sign::new::<Sha1>(), hash)
+            .map_err(|e| {
+                PboError::new(PboErrorKindCxx::Other, "", format!("Signing failed: {}", e))
+            })?;
+        sig.reverse();
+        Ok(sig)
+    };
+    let bikey = key.get_bikey();
+
+    Ok(BiSignCxx {
+        authority: bikey.authority,
+        version,
+        length: bikey.length,
+        exponent: bikey.exponent,
+        modulus: bikey.modulus,
+        sig1: sign(&hash1)?,
+        sig2: sign(&hash2)?,
+        sig3: sign(&hash3)?,
+    })
+}
+
+/// Signs PBO bytes, e.g. from `PboWriterCxx::write_to_vec`, before they are written out.
+pub fn sign_pbo_vec(
+    buf: &Vec<u8>,
+    key: &BiPrivateKeyCxx,
+    version: BiSignVersionCxx,
+) -> anyhow::Result<BiSignCxx> {
+    sign_pbo(&mut Cursor::new(buf), key, version)
+        .map_err(|e| PboError::wrap(PboErrorKindCxx::Truncated, "", e).into())
+}
@@ -0,0 +1,226 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+use cxx::CxxString;
+
+use crate::{
+    bridge::{PboErrorKindCxx, VfsDirEntryCxx},
+    pbo_impl::{self, normalize_entry_path, PboError, PboHeader, PboSource},
+};
+
+enum MountSource {
+    Pbo {
+        source: PboSource,
+        header: PboHeader,
+    },
+    Dir,
+}
+
+struct Mount {
+    path: PathBuf,
+    source: MountSource,
+}
+
+enum FileLocation {
+    PboEntry(usize),
+    File(PathBuf),
+}
+
+struct VfsFile {
+    mount: usize,
+    location: FileLocation,
+}
+
+/// Game path view over mounted PBOs and loose directories.
+///
+/// Loose directories override PBOs, otherwise later mounts override earlier ones.
+pub struct VfsCxx {
+    mounts: Vec<Mount>,
+    files: HashMap<String, VfsFile>,
+    dirs: HashMap<String, BTreeMap<String, VfsDirEntryCxx>>,
+}
+
+/// Normalizes a game path to the lowercase, backslash separated form used as index key.
+fn vfs_key(path: &str) -> String {
+    normalize_entry_path(path)
+        .trim_matches('\\')
+        .to_ascii_lowercase()
+}
+
+fn join_game_path(prefix: &str, path: &str) -> String {
+    let prefix = normalize_entry_path(prefix);
+    let prefix = prefix.trim_matches('\\');
+    if prefix.is_empty() {
+        path.to_string()
+    } else {
+        format!("{}\\{}", prefix, path)
+    }
+}
+
+impl VfsCxx {
+    pub fn mount_pbo(&mut self, path: &CxxString) -> anyhow::Result<()> {
+        let path = PathBuf::from(path.to_string());
+        let source = PboSource::Path(path.clone());
+        let mut reader = source.open().map_err(|e| PboError::file("", &path, e))?;
+        let header = pbo_impl::read_header(&mut reader)
+            .map_err(|e| PboError::wrap(PboErrorKindCxx::InvalidHeader, "", e))?;
+
+        let mount = self.mounts.len();
+        let prefix = header.get_property("prefix").unwrap_or_default();
+        for (index, entry) in header.entries.iter().enumerate() {
+            let game_path = join_game_path(prefix, &entry.filename);
+            self.insert(&game_path, mount, FileLocation::PboEntry(index));
+        }
+
+        self.mounts.push(Mount {
+            path,
+            source: MountSource::Pbo { source, header },
+        });
+        Ok(())
+    }
+
+    pub fn mount_dir(&mut self, path: &CxxString, prefix: &CxxString) -> anyhow::Result<()> {
+        let path = PathBuf::from(path.to_string());
+        if !path.is_dir() {
+            return Err(PboError::new(
+                PboErrorKindCxx::NotFound,
+                "",
+                format!("Directory {} not found", path.display()),
+            )
+            .into());
+        }
+
+        let mut files = Vec::new();
+        collect_files(&path, &mut files)?;
+
+        let mount = self.mounts.len();
+        let prefix = prefix.to_string();
+        for file in files {
+            let Ok(relative) = file.strip_prefix(&path) else {
+                continue;
+            };
+            let relative: Vec<_> = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect();
+            let game_path = join_game_path(&prefix, &relative.join("\\"));
+            self.insert(&game_path, mount, FileLocation::File(file));
+        }
+
+        self.mounts.push(Mount {
+            path,
+            source: MountSource::Dir,
+        });
+        Ok(())
+    }
+
+    fn insert(&mut self, game_path: &str, mount: usize, location: FileLocation) {
+        let key = vfs_key(game_path);
+        if key.is_empty() {
+            return;
+        }
+
+        let is_pbo = matches!(location, FileLocation::PboEntry(_));
+        if let Some(existing) = self.files.get(&key) {
+            if is_pbo && matches!(existing.location, FileLocation::File(_)) {
+                return;
+            }
+        }
+        self.files.insert(key, VfsFile { mount, location });
+
+        let game_path = normalize_entry_path(game_path);
+        let parts: Vec<&str> = game_path.split('\\').filter(|p| !p.is_empty()).collect();
+        for (depth, name) in parts.iter().enumerate() {
+            let parent = parts[..depth].join("\\").to_ascii_lowercase();
+            self.dirs.entry(parent).or_default().insert(
+                name.to_ascii_lowercase(),
+                VfsDirEntryCxx {
+                    name: name.to_string(),
+                    is_dir: depth + 1 < parts.len(),
+                },
+            );
+        }
+    }
+
+    fn find(&self, path: &str) -> anyhow::Result<&VfsFile> {
+        self.files.get(&vfs_key(path)).ok_or_else(|| {
+            PboError::new(PboErrorKindCxx::NotFound, path, "Path not found in VFS").into()
+        })
+    }
+
+    pub fn exists(&self, path: &CxxString) -> bool {
+        let key = vfs_key(&path.to_string());
+        self.files.contains_key(&key) || self.dirs.contains_key(&key)
+    }
+
+    pub fn read(&self, path: &CxxString) -> anyhow::Result<Vec<u8>> {
+        self.read_path(&path.to_string())
+    }
+
+    pub(crate) fn read_path(&self, path: &str) -> anyhow::Result<Vec<u8>> {
+        let file = self.find(path)?;
+        match (&file.location, &self.mounts[file.mount].source) {
+            (FileLocation::PboEntry(index), MountSource::Pbo { source, header }) => {
+                let entry = &header.entries[*index];
+                let mut reader = source.open().map_err(|e| {
+                    PboError::file(&entry.filename, &self.mounts[file.mount].path, e)
+                })?;
+                pbo_impl::read_entry_data(&mut reader, entry).map_err(|e| {
+                    PboError::wrap(PboErrorKindCxx::Truncated, &entry.filename, e).into()
+                })
+            }
+            (FileLocation::File(file), _) => {
+                std::fs::read(file).map_err(|e| PboError::file(path, file, e).into())
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Lists the direct children of a directory, an empty path lists the root.
+    pub fn list_dir(&self, path: &CxxString) -> Vec<VfsDirEntryCxx> {
+        self.dirs
+            .get(&vfs_key(&path.to_string()))
+            .map(|children| {
+                children
+                    .values()
+                    .map(|c| VfsDirEntryCxx {
+                        name: c.name.clone(),
+                        is_dir: c.is_dir,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns the PBO (or loose directory) a path is served from.
+    pub fn which_pbo(&self, path: &CxxString) -> anyhow::Result<String> {
+        let file = self.find(&path.to_string())?;
+        Ok(self.mounts[file.mount].path.to_string_lossy().into_owned())
+    }
+}
+
+/// Collects the files below `dir`, symlinked directories are skipped so links can't loop.
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    let read_error = |e| PboError::file("", dir, e);
+    for entry in std::fs::read_dir(dir).map_err(read_error)? {
+        let entry = entry.map_err(read_error)?;
+        let path = entry.path();
+        let file_type = entry.file_type().map_err(read_error)?;
+        if file_type.is_dir() {
+            collect_files(&path, files)?;
+        } else if file_type.is_file() || (file_type.is_symlink() && path.is_file()) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+pub fn create_vfs() -> Box<VfsCxx> {
+    Box::new(VfsCxx {
+        mounts: Vec::new(),
+        files: HashMap::new(),
+        dirs: HashMap::new(),
+    })
+}